@@ -3,37 +3,41 @@
 //! session layer of a pool client
 
 use crate::message::{
-    ClientCommand, Credentials, ErrorReply, Job, JsonMessage, PoolCommand, PoolEvent, PoolReply,
-    PoolRequest, Share, ShareCustom, WorkerId,
+    ClientCommand, Credentials, ErrorReply, Extensions, Job, JsonMessage, PoolCommand, PoolEvent,
+    PoolReply, PoolRequest, Share, ShareCustom, WorkerId,
 };
 
+use super::transport::{Transport, TransportRead, TransportWrite};
+
 use serde_json;
 
 use std::convert::From;
 use std::default::Default;
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::net::TcpStream;
 use std::time::Duration;
 
 use failure::Fail;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use serde_derive::{Deserialize, Serialize};
 
 /// Result of client operation.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How long to wait for a pool to answer the login.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Id for matching our requests with server replies.
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
 pub struct RequestId(u32);
 
 /// Write-end of a connection to a pool.
 struct ClientWriter {
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Box<dyn TransportWrite>>,
     next_id: RequestId,
 }
 
 impl ClientWriter {
-    fn new(stream: BufWriter<TcpStream>) -> Self {
+    fn new(stream: BufWriter<Box<dyn TransportWrite>>) -> Self {
         ClientWriter {
             stream,
             next_id: RequestId(1),
@@ -57,12 +61,12 @@ impl ClientWriter {
 
 /// Read-end of a connection to a pool.
 pub struct PoolClientReader {
-    stream: BufReader<TcpStream>,
+    stream: BufReader<Box<dyn TransportRead>>,
     buf: String,
 }
 
 impl PoolClientReader {
-    fn new(stream: BufReader<TcpStream>) -> PoolClientReader {
+    fn new(stream: BufReader<Box<dyn TransportRead>>) -> PoolClientReader {
         PoolClientReader {
             stream,
             buf: Default::default(),
@@ -90,11 +94,21 @@ impl PoolClientReader {
 pub struct PoolClientWriter {
     writer: ClientWriter,
     worker_id: WorkerId,
+    extensions: Extensions,
 }
 
 impl PoolClientWriter {
-    fn new(writer: ClientWriter, worker_id: WorkerId) -> Self {
-        PoolClientWriter { writer, worker_id }
+    fn new(writer: ClientWriter, worker_id: WorkerId, extensions: Extensions) -> Self {
+        PoolClientWriter {
+            writer,
+            worker_id,
+            extensions,
+        }
+    }
+
+    /// Protocol extensions the pool listed in its login reply.
+    pub fn extensions(&self) -> Extensions {
+        self.extensions
     }
 
     /// Send a keepalive message.
@@ -131,23 +145,86 @@ impl PoolClientWriter {
         }))
         // 1 PoolReply::StatusReply expected
     }
+
+    /// Close the connection; the read end sees it end.
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.writer.stream.get_mut().shutdown()?;
+        Ok(())
+    }
 }
 
-/// synchronously login to server
-pub fn connect(
-    address: &str,
+/// Maps an algorithm name a pool may use, such as an alias, to the name it stands for, or `None`
+/// if it is unknown.
+pub type AlgorithmResolver = fn(&str) -> Option<&'static str>;
+
+/// Fail if `job` names an algorithm other than one of `algorithms`. With `resolve`, names are
+/// compared by what they resolve to, so an alias matches the name it stands for.
+pub fn check_algorithm(
+    job: &Job,
+    algorithms: &[String],
+    resolve: Option<AlgorithmResolver>,
+) -> Result<()> {
+    let algo = match job.algo() {
+        Some(algo) => algo,
+        None => return Ok(()),
+    };
+    let supported = match resolve {
+        Some(resolve) => {
+            let algo = resolve(algo);
+            algo.is_some() && algorithms.iter().any(|a| resolve(a) == algo)
+        }
+        None => algorithms.iter().any(|a| a == algo),
+    };
+    if supported {
+        Ok(())
+    } else {
+        Err(Error::unsupported_algorithm(algo))
+    }
+}
+
+/// Read until the pool sends a job that passes `check_algorithm`, skipping the others, and
+/// sending keepalives meanwhile.
+fn next_supported_job(
+    reader: &mut PoolClientReader,
+    writer: &mut PoolClientWriter,
+    algorithms: &[String],
+    resolve: Option<AlgorithmResolver>,
+) -> Result<Job> {
+    loop {
+        match reader.read()? {
+            Some(PoolEvent::ClientCommand(ClientCommand::Job(job))) => {
+                match check_algorithm(&job, algorithms, resolve) {
+                    Ok(()) => return Ok(job),
+                    Err(e) => error!("skipping job {}: {}", job.job_id(), e),
+                }
+            }
+            Some(PoolEvent::PoolReply { .. }) => debug!("ignoring reply while waiting for a job"),
+            None => {
+                writer.keepalive()?;
+            }
+        }
+    }
+}
+
+/// synchronously login to server, advertising `algorithms`
+///
+/// If the job assigned at login is for another algorithm, the connection is kept, and the job
+/// returned is the first one the pool then sends for one of `algorithms`.
+pub fn login(
+    transport: Box<dyn Transport>,
     login: &str,
     pass: &str,
     agent: &str,
+    algorithms: &[String],
+    resolve: Option<AlgorithmResolver>,
     keepalive: Option<Duration>,
 ) -> Result<(PoolClientWriter, Job, PoolClientReader)> {
-    let stream_r = TcpStream::connect(address)?;
-    let stream_w = stream_r.try_clone()?;
+    let (mut stream_r, stream_w) = transport.split()?;
+    stream_r.set_read_timeout(Some(LOGIN_TIMEOUT))?;
 
-    stream_w.set_nodelay(true)?;
     let stream_w = BufWriter::with_capacity(1500, stream_w);
     let mut writer = ClientWriter::new(stream_w);
-    let algo = vec!["cn/1".to_owned()];
+    let algo = algorithms.to_vec();
     let (login, pass, agent) = (login.to_owned(), pass.to_owned(), agent.to_owned());
     let req_id = writer.send(PoolCommand::Login(Credentials {
         login,
@@ -157,10 +234,9 @@ pub fn connect(
     }))?;
     debug!("login sent: {:?}", req_id);
 
-    stream_r.set_read_timeout(keepalive)?;
     let stream_r = BufReader::with_capacity(1500, stream_r);
     let mut reader = PoolClientReader::new(stream_r);
-    let (wid, job, status) = loop {
+    let (wid, job, status, extensions) = loop {
         match reader.read()?.ok_or_else(Error::login_timed_out)? {
             PoolEvent::PoolReply {
                 id,
@@ -170,8 +246,9 @@ pub fn connect(
                 debug_assert_eq!(id, req_id);
                 let worker_id = assignment.worker_id();
                 let status = assignment.status().map(|x| x.to_owned());
+                let extensions = assignment.extensions();
                 let job = assignment.into_job();
-                break (worker_id, job, status);
+                break (worker_id, job, status, extensions);
             }
             PoolEvent::PoolReply { error: Some(e), .. } => {
                 return Err(Error(Error_::ErrorReply(e)))
@@ -184,8 +261,17 @@ pub fn connect(
         };
     };
     info!("login successful: status \"{:?}\"", status);
-
-    let writer = PoolClientWriter::new(writer, wid);
+    debug!("pool extensions: {:?}", extensions);
+    reader.stream.get_mut().set_read_timeout(keepalive)?;
+
+    let mut writer = PoolClientWriter::new(writer, wid, extensions);
+    let job = match check_algorithm(&job, algorithms, resolve) {
+        Ok(()) => job,
+        Err(e) => {
+            error!("{}, waiting for a job for {}", e, algorithms.join(", "));
+            next_supported_job(&mut reader, &mut writer, algorithms, resolve)?
+        }
+    };
     Ok((writer, job, reader))
 }
 
@@ -211,6 +297,12 @@ pub enum Error_ {
     LoginUnexpectedReply,
     #[fail(display = "server reports error: {}", _0)]
     ErrorReply(ErrorReply),
+    #[fail(display = "no pools to connect to")]
+    NoPools,
+    #[fail(display = "unsupported address scheme {}", _0)]
+    UnsupportedScheme(String),
+    #[fail(display = "pool sent a job for unsupported algorithm {}", _0)]
+    UnsupportedAlgorithm(String),
 }
 
 impl Error {
@@ -223,6 +315,15 @@ impl Error {
     fn login_unexpected_reply() -> Self {
         Error(Error_::LoginUnexpectedReply)
     }
+    pub(crate) fn no_pools() -> Self {
+        Error(Error_::NoPools)
+    }
+    pub(crate) fn unsupported_scheme(scheme: &str) -> Self {
+        Error(Error_::UnsupportedScheme(scheme.to_owned()))
+    }
+    fn unsupported_algorithm(algo: &str) -> Self {
+        Error(Error_::UnsupportedAlgorithm(algo.to_owned()))
+    }
 }
 
 impl From<io::Error> for Error {
@@ -257,13 +358,145 @@ mod tests {
         r#"00000000019be9ee931ce265444a4d5b599d1e463f1f7fbada6517218fe65aea3a73390a406","#,
         r#""job_id":"12022","target":"b7d10000"},"status":"OK"},"error":null}"#
     );
+    static EXAMPLE_RX_LOGINREPLY_STR: &str = concat!(
+        r#"{"id":1,"jsonrpc":"2.0","result":{"id":"0","job":"#,
+        r#"{"blob":"00","job_id":"1","target":"b7d10000","algo":"rx/0","height":1,"#,
+        r#""seed_hash":"00"},"extensions":["algo","keepalive","connect"],"status":"OK"},"#,
+        r#""error":null}"#
+    );
     static EXAMPLE_JOBCOMMAND_STR: &'static str = concat!(
         r#"{"jsonrpc":"2.0","method":"job","params":"#,
         r#"{"blob":"06068795b8d0055b9272a308e09675e9c4c1510e84921e1ff0bfa13fc375eb8eec2207408205c"#,
         r#"000000000da5d4af05371b7bda75eef0d73cbbead3773006bd9117b1ca7dbcc9dacc1284d0d","#,
-        r#""job_id":"12023","target":"b7d10000"}}"#
+        r#""job_id":"12023","target":"b7d10000","height":2182450,"#,
+        r#""seed_hash":"308c6f50a99d6854394ea0e471cbd5234a29554a86df1f6708a4cbe2093a4078"}}"#
+    );
+    static EXAMPLE_RX_JOBCOMMAND_STR: &'static str = concat!(
+        r#"{"jsonrpc":"2.0","method":"job","params":"#,
+        r#"{"blob":"0c0cbbd9dffa056ed9f488ea952afa3ff2663b1ec70a60baea5543c2bab0f25a9e830d2d40eb5f"#,
+        r#"000000009caec86efcd1554b50015f58db69445c62381943a4385ce402ae15ded652657019","#,
+        r#""job_id":"12024","target":"c5a70000","algo":"rx/0","height":2182450,"#,
+        r#""seed_hash":"308c6f50a99d6854394ea0e471cbd5234a29554a86df1f6708a4cbe2093a4078","#,
+        r#""next_seed_hash":"cf1e1ae3ee4f2e5d1f9a9c56ff4f1ec0b07a2b7d1ec0d6bd7b3d1a1c1e0e6b4a"}}"#
+    );
+
+    static EXAMPLE_WOW_JOBCOMMAND_STR: &str = concat!(
+        r#"{"jsonrpc":"2.0","method":"job","params":"#,
+        r#"{"blob":"00","job_id":"12025","target":"c5a70000","algo":"rx/wow","height":1,"#,
+        r#""seed_hash":"00"}}"#
     );
 
+    #[test]
+    fn login_over_pipe() {
+        let (client, server) = crate::client::pipe();
+        let server = std::thread::spawn(move || {
+            let (reader, mut writer) = Box::new(server).split().unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            let login = lines.next().unwrap().unwrap();
+            assert!(login.contains(r#""method":"login""#));
+            assert!(login.contains(r#""algo":["rx/0","rx/wow"]"#));
+            writeln!(writer, "{}", crate::client::tests::login_reply("1")).unwrap();
+            let keepalive = lines.next().unwrap().unwrap();
+            assert!(keepalive.contains(r#""method":"keepalived""#));
+            // the client shut the connection down
+            assert!(lines.next().is_none());
+        });
+        let keepalive = Some(Duration::from_millis(10));
+        let algorithms = ["rx/0".to_owned(), "rx/wow".to_owned()];
+        let (mut writer, job, mut reader) = login(
+            Box::new(client),
+            "login",
+            "x",
+            "test",
+            &algorithms,
+            None,
+            keepalive,
+        )
+        .unwrap();
+        assert_eq!(job.job_id(), "1");
+        assert_eq!(writer.extensions(), Extensions::default());
+        assert!(reader.read().unwrap().is_none());
+        writer.keepalive().unwrap();
+        writer.shutdown().unwrap();
+        assert!(reader.read().is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn unsupported_algorithm() {
+        let (client, server) = crate::client::pipe();
+        let server = std::thread::spawn(move || {
+            let (reader, mut writer) = Box::new(server).split().unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            lines.next().unwrap().unwrap();
+            writeln!(writer, "{}", EXAMPLE_RX_LOGINREPLY_STR).unwrap();
+            // the client keeps the connection while it waits for a job it can mine
+            let keepalive = lines.next().unwrap().unwrap();
+            assert!(keepalive.contains(r#""method":"keepalived""#));
+            writeln!(writer, "{}", EXAMPLE_RX_JOBCOMMAND_STR).unwrap();
+            writeln!(writer, "{}", EXAMPLE_WOW_JOBCOMMAND_STR).unwrap();
+            lines.next();
+        });
+        let keepalive = Some(Duration::from_millis(10));
+        let algorithms = ["rx/wow".to_owned()];
+        let (mut writer, job, _) = login(
+            Box::new(client),
+            "login",
+            "x",
+            "test",
+            &algorithms,
+            None,
+            keepalive,
+        )
+        .unwrap();
+        assert_eq!(job.job_id(), "12025");
+        assert_eq!(job.algo(), Some("rx/wow"));
+        writer.shutdown().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn aliased_algorithm() {
+        fn resolve(name: &str) -> Option<&'static str> {
+            match name {
+                "rx/0" | "rx/monero" => Some("rx/0"),
+                "rx/wow" => Some("rx/wow"),
+                _ => None,
+            }
+        }
+        let job = |algo: &str| -> Job {
+            let json = EXAMPLE_RX_JOBCOMMAND_STR.replace(r#""algo":"rx/0""#, algo);
+            match serde_json::from_str(&json).unwrap() {
+                PoolEvent::<u32>::ClientCommand(ClientCommand::Job(job)) => job,
+                _ => panic!("expected a job command"),
+            }
+        };
+        let rx0 = ["rx/0".to_owned()];
+        let aliased = job(r#""algo":"rx/monero""#);
+        assert!(check_algorithm(&aliased, &rx0, Some(resolve)).is_ok());
+        assert!(check_algorithm(&aliased, &rx0, None).is_err());
+        assert!(check_algorithm(&aliased, &["rx/wow".to_owned()], Some(resolve)).is_err());
+        let unknown = job(r#""algo":"cn/r""#);
+        assert!(check_algorithm(&unknown, &["cn/r".to_owned()], Some(resolve)).is_err());
+    }
+
+    #[test]
+    fn deserialize_extensions() {
+        let event: PoolEvent<u32> = serde_json::from_str(EXAMPLE_RX_LOGINREPLY_STR).unwrap();
+        let assignment = match event {
+            PoolEvent::PoolReply {
+                result: Some(PoolReply::Job(assignment)),
+                ..
+            } => assignment,
+            _ => panic!("expected a login reply"),
+        };
+        let extensions = assignment.extensions();
+        assert!(extensions.algo && extensions.keepalive && !extensions.nicehash);
+        let job = assignment.into_job();
+        assert!(check_algorithm(&job, &["rx/0".to_owned()], None).is_ok());
+        assert!(check_algorithm(&job, &["rx/wow".to_owned()], None).is_err());
+    }
+
     #[test]
     fn deserialize_login_reply() {
         let _: PoolEvent<u32> = serde_json::from_str(EXAMPLE_LOGINREPLY_STR).unwrap();
@@ -273,4 +506,17 @@ mod tests {
     fn deserialize_job_command() {
         let _: PoolEvent<u32> = serde_json::from_str(EXAMPLE_JOBCOMMAND_STR).unwrap();
     }
+
+    #[test]
+    fn deserialize_next_seed_hash() {
+        let event: PoolEvent<u32> = serde_json::from_str(EXAMPLE_RX_JOBCOMMAND_STR).unwrap();
+        let job = match event {
+            PoolEvent::ClientCommand(ClientCommand::Job(job)) => job,
+            _ => panic!("expected a job command"),
+        };
+        assert_eq!(
+            job.next_seed_hash(),
+            Some("cf1e1ae3ee4f2e5d1f9a9c56ff4f1ec0b07a2b7d1ec0d6bd7b3d1a1c1e0e6b4a")
+        );
+    }
 }
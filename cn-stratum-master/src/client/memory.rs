@@ -0,0 +1,140 @@
+// copyright 2017 Kaz Wesley
+
+//! in-memory transport, for testing the session layer without sockets
+
+use super::transport::{Transport, TransportRead, TransportWrite};
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bytes travelling one way.
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_all();
+    }
+}
+
+/// One end of an in-memory connection; see `pipe()`.
+pub struct MemoryTransport {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+}
+
+/// Two connected in-memory transports: what is written to one is read from the other.
+pub fn pipe() -> (MemoryTransport, MemoryTransport) {
+    let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+    let left = MemoryTransport {
+        incoming: Arc::clone(&a),
+        outgoing: Arc::clone(&b),
+    };
+    let right = MemoryTransport {
+        incoming: b,
+        outgoing: a,
+    };
+    (left, right)
+}
+
+impl Transport for MemoryTransport {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn TransportRead>, Box<dyn TransportWrite>)> {
+        let reader = MemoryReader {
+            pipe: Arc::clone(&self.incoming),
+            timeout: None,
+        };
+        let writer = MemoryWriter {
+            incoming: self.incoming,
+            outgoing: self.outgoing,
+        };
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+struct MemoryReader {
+    pipe: Arc<Pipe>,
+    timeout: Option<Duration>,
+}
+
+impl Read for MemoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.pipe.state.lock().unwrap();
+        while state.data.is_empty() && !state.closed {
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    self.pipe
+                        .readable
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.pipe.readable.wait(state).unwrap(),
+            };
+        }
+        let len = buf.len().min(state.data.len());
+        for (dst, src) in buf.iter_mut().zip(state.data.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl TransportRead for MemoryReader {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+struct MemoryWriter {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.data.extend(buf);
+        self.outgoing.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TransportWrite for MemoryWriter {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.incoming.close();
+        self.outgoing.close();
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    /// Like dropping a socket, the peer reads the end of the stream.
+    fn drop(&mut self) {
+        self.outgoing.close();
+    }
+}
@@ -4,15 +4,26 @@
 //! multiplex lots of upstreams.
 
 mod connection;
+mod memory;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
 
-use self::connection::PoolClientReader;
+use self::connection::{check_algorithm, PoolClientReader};
 use crate::message::{ClientCommand, PoolEvent, PoolReply};
 
-pub use self::connection::{PoolClientWriter, RequestId, Result};
-pub use crate::message::{ErrorReply, Job, JobAssignment};
+pub use self::connection::{AlgorithmResolver, Error, PoolClientWriter, RequestId, Result};
+pub use self::memory::{pipe, MemoryTransport};
+pub use self::transport::{Connector, Transport, TransportRead, TransportWrite};
+pub use crate::message::{ErrorReply, Extensions, Job, JobAssignment};
 
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use log::*;
 
@@ -22,13 +33,152 @@ pub trait MessageHandler {
     fn error_reply(&mut self, id: RequestId, error: ErrorReply);
     fn status_reply(&mut self, id: RequestId, status: String);
     fn job_reply(&mut self, id: RequestId, job: Box<JobAssignment>);
+
+    /// The client logged in to the pool at `address`: first on connecting, then after every
+    /// reconnect or switch to another pool.
+    fn logged_in(&mut self, _address: &str) {}
+
+    /// The connection was lost; the client tries to reconnect.
+    fn disconnected(&mut self, _error: &Error) {}
+
+    /// The client logged in again after a disconnect or a switch to another pool, and the pool
+    /// assigned `job`.
+    fn reconnected(&mut self, job: Job) {
+        self.job_command(job);
+    }
+}
+
+/// How long to wait between attempts to reconnect to a pool.
+///
+/// The delay before attempt `n` (from 0) is `initial * multiplier^n`, capped at `max`, and then
+/// shortened by a random fraction of up to `jitter` so that many miners dropped at once do not
+/// come back in lockstep.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: f64,
+    /// Between 0 (no jitter) and 1.
+    pub jitter: f64,
+    /// Number of failed attempts after which the client fails over to the next pool, or `run()`
+    /// gives up if there is no other pool; 0 counts as 1. `None` to retry forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before attempt `attempt`, given a random `unit` in [0, 1).
+    fn delay(&self, attempt: u32, unit: f64) -> Duration {
+        let base = self.initial.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let base = base.min(self.max.as_secs_f64());
+        Duration::from_secs_f64(base * (1.0 - self.jitter * unit))
+    }
+}
+
+/// A random number in [0, 1), from the randomly keyed hasher of std.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u128(now.as_nanos());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
 }
 
+/// How to check the certificate of a `stratum+ssl://` pool. By default it must chain to one of
+/// the Mozilla root certificates and match the host name.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// SHA-256 fingerprint of the pool's certificate. If set, that certificate is accepted, and
+    /// no other, whoever issued it.
+    pub fingerprint: Option<[u8; 32]>,
+    /// Accept any certificate, e.g. of a self-signed proxy on a trusted network.
+    pub insecure: bool,
+}
+
+/// A pool to log in to, and how to reconnect to it. The address is `host:port`,
+/// `stratum+ssl://host:port` for TLS, or `unix:///path` for a Unix domain socket.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub address: String,
+    pub login: String,
+    pub pass: String,
+    pub keepalive: Option<Duration>,
+    pub policy: ReconnectPolicy,
+    pub tls: TlsOptions,
+    /// Opens the connection instead of dialing `address`, which then only names the pool.
+    pub connector: Option<Connector>,
+    /// Algorithms to offer the pool at login, e.g. `rx/0`. Jobs for any other algorithm are
+    /// skipped, including the one assigned at login; see `connection::login`.
+    pub algorithms: Vec<String>,
+    /// Resolves the algorithm names of jobs and `algorithms` before they are compared, so that a
+    /// job may name an alias. `None` to compare the names as they are.
+    pub resolve_algorithm: Option<AlgorithmResolver>,
+}
+
+impl Pool {
+    pub fn new(address: &str, login: &str, pass: &str, keepalive: Option<Duration>) -> Self {
+        Pool {
+            address: address.to_owned(),
+            login: login.to_owned(),
+            pass: pass.to_owned(),
+            keepalive,
+            policy: ReconnectPolicy::default(),
+            tls: TlsOptions::default(),
+            connector: None,
+            algorithms: vec!["rx/0".to_owned()],
+            resolve_algorithm: None,
+        }
+    }
+
+    fn connect(&self, agent: &str) -> Result<Connection> {
+        let transport = match &self.connector {
+            Some(connector) => connector.connect()?,
+            None => transport::dial(&self.address, &self.tls)?,
+        };
+        connection::login(
+            transport,
+            &self.login,
+            &self.pass,
+            agent,
+            &self.algorithms,
+            self.resolve_algorithm,
+            self.keepalive,
+        )
+    }
+}
+
+/// A logged-in connection: the write end, the job assigned at login, and the read end.
+type Connection = (PoolClientWriter, Job, PoolClientReader);
+
 /// A synchronous stratum pool client, customized with a MessageHandler.
+///
+/// The client fails over between pools in order of preference, and while it is logged in to any
+/// but the first, it tries every `failback` interval to move back to a more preferred one.
 pub struct PoolClient<H> {
     writer: Arc<Mutex<PoolClientWriter>>,
     reader: PoolClientReader,
     handler: H,
+    agent: String,
+    pools: Vec<Pool>,
+    /// Index of the pool the client is logged in to, or trying to reconnect to.
+    active: usize,
+    failback: Duration,
+    next_failback: Instant,
+    /// Reports the first more preferred pool that accepted a login from the failback thread, if
+    /// one is running.
+    probe: Option<Receiver<(usize, Connection)>>,
 }
 
 impl<H: MessageHandler> PoolClient<H> {
@@ -44,18 +194,64 @@ impl<H: MessageHandler> PoolClient<H> {
     where
         F: FnOnce(Job) -> H,
     {
-        let (writer, work, reader) = connection::connect(address, login, pass, agent, keepalive)?;
-        debug!("client connected, initial job: {:?}", &work);
-        let writer = Arc::new(Mutex::new(writer));
-        let handler = make_handler(work);
-        Ok(PoolClient {
-            writer,
-            reader,
-            handler,
-        })
+        let pool = Pool::new(address, login, pass, keepalive);
+        PoolClient::connect_pools(vec![pool], agent, make_handler)
     }
 
-    /// Return a new handle to the write end of the client connection.
+    /// Synchronously connect to the first of `pools`, most preferred first, that accepts the
+    /// login; pass the initial job to a MessageHandler constructor.
+    pub fn connect_pools<F>(pools: Vec<Pool>, agent: &str, make_handler: F) -> Result<Self>
+    where
+        F: FnOnce(Job) -> H,
+    {
+        let mut last_error = Error::no_pools();
+        for (active, pool) in pools.iter().enumerate() {
+            let (writer, work, reader) = match pool.connect(agent) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("failed to connect to {}: {}", pool.address, e);
+                    last_error = e;
+                    continue;
+                }
+            };
+            debug!("client connected, initial job: {:?}", &work);
+            let writer = Arc::new(Mutex::new(writer));
+            let mut handler = make_handler(work);
+            handler.logged_in(&pool.address);
+            let failback = Duration::from_secs(60);
+            return Ok(PoolClient {
+                writer,
+                reader,
+                handler,
+                agent: agent.to_owned(),
+                pools,
+                active,
+                failback,
+                next_failback: Instant::now() + failback,
+                probe: None,
+            });
+        }
+        Err(last_error)
+    }
+
+    /// Set how to reconnect to every pool when the connection is lost.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        for pool in &mut self.pools {
+            pool.policy = policy.clone();
+        }
+        self
+    }
+
+    /// Set how often to try to move back to a more preferred pool. The pools are tried on another
+    /// thread, and the client moves with the next message or keepalive after one accepts.
+    pub fn failback(mut self, interval: Duration) -> Self {
+        self.failback = interval;
+        self.next_failback = Instant::now() + interval;
+        self
+    }
+
+    /// Return a new handle to the write end of the client connection. The handle stays valid
+    /// across reconnects; while the client is disconnected, writes through it fail.
     pub fn write_handle(&self) -> Arc<Mutex<PoolClientWriter>> {
         Arc::clone(&self.writer)
     }
@@ -65,18 +261,135 @@ impl<H: MessageHandler> PoolClient<H> {
         &self.handler
     }
 
-    /// Handle messages until the connection is closed.
+    /// Address of the pool the client is logged in to.
+    pub fn pool(&self) -> &str {
+        &self.pools[self.active].address
+    }
+
+    /// Handle messages, logging in again whenever the connection is lost. Returns only if the
+    /// reconnect policy of the only pool gives up.
     pub fn run(mut self) -> Result<()> {
+        loop {
+            if let Err(e) = self.serve() {
+                warn!("connection to {} lost: {}", self.pool(), e);
+                self.handler.disconnected(&e);
+                self.reconnect()?;
+            }
+        }
+    }
+
+    /// Reconnect with backoff, failing over to the next pool when the policy of the current one
+    /// runs out of attempts.
+    fn reconnect(&mut self) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let pool = &self.pools[self.active];
+            let delay = pool.policy.delay(attempt, random_unit());
+            info!("reconnecting to {} in {:?}", pool.address, delay);
+            thread::sleep(delay);
+            attempt += 1;
+            match pool.connect(&self.agent) {
+                Ok(connection) => {
+                    info!("reconnected after {} attempt(s)", attempt);
+                    self.switch(self.active, connection);
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("reconnect attempt {} failed: {}", attempt, e);
+                    if matches!(pool.policy.max_attempts, Some(max) if attempt >= max) {
+                        if self.pools.len() == 1 {
+                            return Err(e);
+                        }
+                        self.active = (self.active + 1) % self.pools.len();
+                        attempt = 0;
+                        info!("failing over to {}", self.pool());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move to a more preferred pool if the failback thread found one, and start the thread again
+    /// when the interval is due. The thread tries once to log in to each pool more preferred than
+    /// the current one, so a pool that is slow to answer does not hold up this connection.
+    fn failback_if_due(&mut self) {
+        if let Some(probe) = &self.probe {
+            match probe.try_recv() {
+                Ok((index, connection)) => {
+                    self.probe = None;
+                    // a reconnect may have moved the client to that pool or a better one meanwhile
+                    if index < self.active {
+                        let address = &self.pools[index].address;
+                        info!("{} is back, moving from {}", address, self.pool());
+                        self.switch(index, connection);
+                    }
+                    return;
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.probe = None,
+            }
+        }
+        if self.active == 0 || Instant::now() < self.next_failback {
+            return;
+        }
+        self.next_failback = Instant::now() + self.failback;
+        let pools = self.pools[..self.active].to_vec();
+        let agent = self.agent.clone();
+        let (tx, rx) = mpsc::channel();
+        let probe = thread::Builder::new()
+            .name("failback".into())
+            .spawn(move || {
+                for (index, pool) in pools.iter().enumerate() {
+                    match pool.connect(&agent) {
+                        Ok(connection) => {
+                            if tx.send((index, connection)).is_err() {
+                                debug!("client is gone, closing connection to {}", pool.address);
+                            }
+                            return;
+                        }
+                        Err(e) => debug!("{} still unavailable: {}", pool.address, e),
+                    }
+                }
+            });
+        match probe {
+            Ok(_) => self.probe = Some(rx),
+            Err(e) => warn!("failed to start failback thread: {}", e),
+        }
+    }
+
+    /// Swap a new connection to pool `index` in behind the write handle.
+    fn switch(&mut self, index: usize, connection: Connection) {
+        let (writer, work, reader) = connection;
+        let mut previous = mem::replace(&mut *self.writer.lock().unwrap(), writer);
+        if let Err(e) = previous.shutdown() {
+            debug!("closing previous connection: {}", e);
+        }
+        self.reader = reader;
+        self.active = index;
+        self.next_failback = Instant::now() + self.failback;
+        self.handler.logged_in(&self.pools[index].address);
+        self.handler.reconnected(work);
+    }
+
+    /// Handle messages until the connection fails.
+    fn serve(&mut self) -> Result<()> {
         loop {
             let event = if let Some(event) = self.reader.read()? {
                 event
             } else {
                 debug!("read timeout; sending keepalive");
-                self.writer.lock().unwrap().keepalive().unwrap();
+                self.writer.lock().unwrap().keepalive()?;
+                self.failback_if_due();
                 continue;
             };
             match event {
-                PoolEvent::ClientCommand(ClientCommand::Job(j)) => self.handler.job_command(j),
+                PoolEvent::ClientCommand(ClientCommand::Job(j)) => {
+                    let pool = &self.pools[self.active];
+                    match check_algorithm(&j, &pool.algorithms, pool.resolve_algorithm) {
+                        Ok(()) => self.handler.job_command(j),
+                        Err(e) => error!("skipping job {} from {}: {}", j.job_id(), self.pool(), e),
+                    }
+                }
                 PoolEvent::PoolReply {
                     id,
                     error: Some(error),
@@ -98,6 +411,325 @@ impl<H: MessageHandler> PoolClient<H> {
                     ..
                 } => warn!("pool reply with no content"),
             }
+            self.failback_if_due();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{self, Sender};
+
+    #[test]
+    fn reconnect_delay() {
+        let policy = ReconnectPolicy::default();
+        assert_eq!(policy.delay(0, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_secs(8));
+        assert_eq!(policy.delay(100, 0.0), Duration::from_secs(60));
+        assert_eq!(policy.delay(3, 0.5), Duration::from_secs(6));
+        for _ in 0..100 {
+            let unit = random_unit();
+            assert!((0.0..1.0).contains(&unit));
+            let delay = policy.delay(1, unit);
+            assert!(delay > Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    struct Events(Sender<String>);
+
+    impl MessageHandler for Events {
+        fn job_command(&mut self, job: Job) {
+            self.0.send(format!("job {}", job.job_id())).unwrap();
+        }
+        fn error_reply(&mut self, _id: RequestId, _error: ErrorReply) {}
+        fn status_reply(&mut self, _id: RequestId, _status: String) {}
+        fn job_reply(&mut self, _id: RequestId, _job: Box<JobAssignment>) {}
+        fn logged_in(&mut self, address: &str) {
+            self.0.send(format!("pool {}", address)).unwrap();
+        }
+        fn disconnected(&mut self, _error: &Error) {
+            self.0.send("disconnected".to_owned()).unwrap();
         }
     }
+
+    /// Reply to the first login with job `job_id`.
+    pub(crate) fn login_reply(job_id: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"id":1,"jsonrpc":"2.0","result":{{"id":"0","job":{{"blob":"00","#,
+                r#""job_id":"{}","target":"b7d10000","height":1,"seed_hash":"00"}},"#,
+                r#""status":"OK"}},"error":null}}"#
+            ),
+            job_id
+        )
+    }
+
+    /// Job notification with job `job_id` for `algo`.
+    fn job_command(job_id: &str, algo: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"jsonrpc":"2.0","method":"job","params":{{"blob":"00","job_id":"{}","#,
+                r#""target":"b7d10000","algo":"{}","height":1,"seed_hash":"00"}}}}"#
+            ),
+            job_id, algo
+        )
+    }
+
+    /// Accept a connection and answer its login with job `job_id`.
+    fn accept_login(listener: &TcpListener, job_id: &str) -> (TcpStream, BufReader<TcpStream>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains(r#""method":"login""#));
+        writeln!(stream, "{}", login_reply(job_id)).unwrap();
+        (stream, reader)
+    }
+
+    #[test]
+    fn reconnect_after_disconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = thread::spawn(move || {
+            drop(accept_login(&listener, "1"));
+            let (_stream, mut reader) = accept_login(&listener, "2");
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let client = PoolClient::connect(&address, "login", "x", None, "test", |job| {
+            tx.send(format!("job {}", job.job_id())).unwrap();
+            Events(tx)
+        })
+        .unwrap()
+        .reconnect_policy(ReconnectPolicy {
+            initial: Duration::from_millis(10),
+            ..Default::default()
+        });
+        let writer = client.write_handle();
+        thread::spawn(move || client.run());
+
+        assert_eq!(rx.recv().unwrap(), "job 1");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", address));
+        assert_eq!(rx.recv().unwrap(), "disconnected");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", address));
+        assert_eq!(rx.recv().unwrap(), "job 2");
+        writer.lock().unwrap().keepalive().unwrap();
+        assert!(pool.join().unwrap().contains(r#""method":"keepalived""#));
+    }
+
+    #[test]
+    fn skip_unsupported_job() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = thread::spawn(move || {
+            let (mut stream, mut reader) = accept_login(&listener, "1");
+            writeln!(stream, "{}", job_command("2", "rx/wow")).unwrap();
+            writeln!(stream, "{}", job_command("3", "rx/0")).unwrap();
+            // the session stays up: no second login
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let client = PoolClient::connect(&address, "login", "x", None, "test", |job| {
+            tx.send(format!("job {}", job.job_id())).unwrap();
+            Events(tx)
+        })
+        .unwrap();
+        let writer = client.write_handle();
+        thread::spawn(move || client.run());
+
+        assert_eq!(rx.recv().unwrap(), "job 1");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", address));
+        assert_eq!(rx.recv().unwrap(), "job 3");
+        writer.lock().unwrap().keepalive().unwrap();
+        assert!(pool.join().unwrap().contains(r#""method":"keepalived""#));
+    }
+
+    #[test]
+    fn aliased_job() {
+        fn resolve(name: &str) -> Option<&'static str> {
+            match name {
+                "rx/0" | "randomx" => Some("rx/0"),
+                _ => None,
+            }
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = thread::spawn(move || {
+            let (mut stream, _reader) = accept_login(&listener, "1");
+            writeln!(stream, "{}", job_command("2", "randomx")).unwrap();
+            writeln!(stream, "{}", job_command("3", "rx/wow")).unwrap();
+            writeln!(stream, "{}", job_command("4", "rx/0")).unwrap();
+            stream
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let pool_config = Pool {
+            resolve_algorithm: Some(resolve),
+            ..Pool::new(&address, "login", "x", None)
+        };
+        let client = PoolClient::connect_pools(vec![pool_config], "test", |job| {
+            tx.send(format!("job {}", job.job_id())).unwrap();
+            Events(tx)
+        })
+        .unwrap();
+        thread::spawn(move || client.run());
+
+        assert_eq!(rx.recv().unwrap(), "job 1");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", address));
+        assert_eq!(rx.recv().unwrap(), "job 2");
+        assert_eq!(rx.recv().unwrap(), "job 4");
+        let _stream = pool.join().unwrap();
+    }
+
+    #[test]
+    fn connector_transport() {
+        // each connection is an in-memory pipe, whose far end goes to the pool thread
+        let (ends, accepted) = mpsc::channel();
+        let ends = Mutex::new(ends);
+        let connector = Connector::new(move || {
+            let (client, server) = pipe();
+            ends.lock().unwrap().send(server).unwrap();
+            Ok(Box::new(client) as Box<dyn Transport>)
+        });
+        let pool = thread::spawn(move || {
+            for job_id in &["1", "2"] {
+                let end: MemoryTransport = accepted.recv().unwrap();
+                let (reader, mut writer) = Box::new(end).split().unwrap();
+                let mut reader = BufReader::new(reader);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert!(line.contains(r#""method":"login""#));
+                writeln!(writer, "{}", login_reply(job_id)).unwrap();
+            }
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let pool_config = Pool {
+            connector: Some(connector),
+            policy: ReconnectPolicy {
+                initial: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Pool::new("memory", "login", "x", None)
+        };
+        let client = PoolClient::connect_pools(vec![pool_config], "test", |job| {
+            tx.send(format!("job {}", job.job_id())).unwrap();
+            Events(tx)
+        })
+        .unwrap();
+        thread::spawn(move || client.run());
+
+        assert_eq!(rx.recv().unwrap(), "job 1");
+        assert_eq!(rx.recv().unwrap(), "pool memory");
+        assert_eq!(rx.recv().unwrap(), "disconnected");
+        assert_eq!(rx.recv().unwrap(), "pool memory");
+        assert_eq!(rx.recv().unwrap(), "job 2");
+        pool.join().unwrap();
+    }
+
+    #[test]
+    fn failover_and_back() {
+        let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+        let primary_address = primary.local_addr().unwrap().to_string();
+        let backup = TcpListener::bind("127.0.0.1:0").unwrap();
+        let backup_address = backup.local_addr().unwrap().to_string();
+        // the primary goes down after the first login
+        let primary = thread::spawn(move || drop(accept_login(&primary, "1")));
+        let backup = thread::spawn(move || {
+            let (_stream, mut reader) = accept_login(&backup, "2");
+            // keepalives, until the client moves back to the primary
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                line.clear();
+            }
+        });
+
+        let pool = |address: &str| Pool {
+            policy: ReconnectPolicy {
+                initial: Duration::from_millis(10),
+                max_attempts: Some(2),
+                ..Default::default()
+            },
+            ..Pool::new(address, "login", "x", Some(Duration::from_millis(50)))
+        };
+        let pools = vec![pool(&primary_address), pool(&backup_address)];
+        let (tx, rx) = mpsc::channel();
+        let client = PoolClient::connect_pools(pools, "test", |job| {
+            tx.send(format!("job {}", job.job_id())).unwrap();
+            Events(tx)
+        })
+        .unwrap()
+        .failback(Duration::from_millis(10));
+        thread::spawn(move || client.run());
+
+        assert_eq!(rx.recv().unwrap(), "job 1");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", primary_address));
+        primary.join().unwrap();
+        assert_eq!(rx.recv().unwrap(), "disconnected");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", backup_address));
+        assert_eq!(rx.recv().unwrap(), "job 2");
+
+        // the primary recovers
+        let primary = TcpListener::bind(&primary_address).unwrap();
+        let primary = thread::spawn(move || accept_login(&primary, "3"));
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", primary_address));
+        assert_eq!(rx.recv().unwrap(), "job 3");
+        backup.join().unwrap();
+        let _connection = primary.join().unwrap();
+    }
+
+    #[test]
+    fn failback_does_not_block_reads() {
+        let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+        let primary_address = primary.local_addr().unwrap().to_string();
+        let backup = TcpListener::bind("127.0.0.1:0").unwrap();
+        let backup_address = backup.local_addr().unwrap().to_string();
+        // the primary drops the first login, and leaves the failback login unanswered
+        let (release, hold) = mpsc::channel::<()>();
+        let primary = thread::spawn(move || {
+            drop(primary.accept().unwrap());
+            let _stream = primary.accept().unwrap();
+            hold.recv().ok();
+        });
+        thread::spawn(move || {
+            let (mut stream, mut reader) = accept_login(&backup, "1");
+            // a job after the first keepalive, once the failback login is under way
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            writeln!(stream, "{}", job_command("2", "rx/0")).unwrap();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                line.clear();
+            }
+        });
+
+        let pool =
+            |address: &str| Pool::new(address, "login", "x", Some(Duration::from_millis(50)));
+        let pools = vec![pool(&primary_address), pool(&backup_address)];
+        let (tx, rx) = mpsc::channel();
+        let client = PoolClient::connect_pools(pools, "test", |job| {
+            tx.send(format!("job {}", job.job_id())).unwrap();
+            Events(tx)
+        })
+        .unwrap()
+        .failback(Duration::from_millis(10));
+        thread::spawn(move || client.run());
+
+        assert_eq!(rx.recv().unwrap(), "job 1");
+        assert_eq!(rx.recv().unwrap(), format!("pool {}", backup_address));
+        // well within the login timeout the failback login is waiting on
+        let job = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(job, "job 2");
+        release.send(()).unwrap();
+        primary.join().unwrap();
+    }
 }
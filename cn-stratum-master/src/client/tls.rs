@@ -0,0 +1,336 @@
+// copyright 2017 Kaz Wesley
+
+//! TLS for `stratum+ssl://` pools, over rustls.
+//!
+//! A rustls session cannot be cloned like a `TcpStream`, so the read and write ends share it
+//! behind a mutex. The reader waits for records on its own handle to the socket without holding
+//! the lock, so a blocked read does not hold up shares being written.
+
+use super::transport::{Transport, TransportRead, TransportWrite};
+use super::TlsOptions;
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::warn;
+use ring::digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, ring as provider, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme,
+};
+
+/// SHA-256 fingerprint of a DER-encoded certificate.
+pub fn fingerprint(certificate: &[u8]) -> [u8; 32] {
+    let mut fingerprint = [0; 32];
+    fingerprint.copy_from_slice(digest::digest(&digest::SHA256, certificate).as_ref());
+    fingerprint
+}
+
+/// Accepts the certificate with the pinned fingerprint, or any certificate if none is pinned.
+/// Handshake signatures are still checked against the certificate's key.
+#[derive(Debug)]
+struct PinnedVerifier {
+    fingerprint: Option<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self.fingerprint {
+            Some(expected) if fingerprint(end_entity) != expected => Err(
+                rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure),
+            ),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn config(options: &TlsOptions) -> Result<ClientConfig, rustls::Error> {
+    let provider = Arc::new(provider::default_provider());
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    if options.fingerprint.is_none() && !options.insecure {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        return Ok(builder.with_root_certificates(roots).with_no_client_auth());
+    }
+    let verifier = PinnedVerifier {
+        fingerprint: options.fingerprint,
+        provider,
+    };
+    Ok(builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A TLS session over TCP.
+pub struct TlsStream {
+    session: ClientConnection,
+    socket: TcpStream,
+}
+
+impl TlsStream {
+    /// Complete a TLS handshake with `host` over `socket`.
+    pub fn connect(mut socket: TcpStream, host: &str, options: &TlsOptions) -> io::Result<Self> {
+        if options.insecure && options.fingerprint.is_none() {
+            warn!("not verifying the certificate of {}", host);
+        }
+        let name = ServerName::try_from(host.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let config = config(options).map_err(invalid_data)?;
+        let mut session = ClientConnection::new(Arc::new(config), name).map_err(invalid_data)?;
+        while session.is_handshaking() {
+            session.complete_io(&mut socket)?;
+        }
+        Ok(TlsStream { session, socket })
+    }
+}
+
+impl Transport for TlsStream {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn TransportRead>, Box<dyn TransportWrite>)> {
+        let session = Arc::new(Mutex::new(self.session));
+        let reader = TlsReader {
+            session: Arc::clone(&session),
+            socket: self.socket.try_clone()?,
+        };
+        let writer = TlsWriter {
+            session,
+            socket: self.socket,
+        };
+        Ok((Box::new(reader), Box::new(writer)))
+    }
+}
+
+/// Read end of a TLS session.
+struct TlsReader {
+    session: Arc<Mutex<ClientConnection>>,
+    socket: TcpStream,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut record = [0; 4096];
+        loop {
+            match self.session.lock().unwrap().reader().read(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                res => return res,
+            }
+            let len = self.socket.read(&mut record)?;
+            let mut session = self.session.lock().unwrap();
+            session.read_tls(&mut &record[..len])?;
+            let processed = session.process_new_packets();
+            // alerts, and replies to key updates
+            while session.wants_write() {
+                session.write_tls(&mut self.socket)?;
+            }
+            processed.map_err(invalid_data)?;
+        }
+    }
+}
+
+/// Write end of a TLS session.
+struct TlsWriter {
+    session: Arc<Mutex<ClientConnection>>,
+    socket: TcpStream,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session.lock().unwrap();
+        let len = session.writer().write(buf)?;
+        while session.wants_write() {
+            session.write_tls(&mut self.socket)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock().unwrap();
+        session.writer().flush()?;
+        while session.wants_write() {
+            session.write_tls(&mut self.socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl TransportRead for TlsReader {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+}
+
+impl TransportWrite for TlsWriter {
+    fn shutdown(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock().unwrap();
+        session.send_close_notify();
+        while session.wants_write() {
+            session.write_tls(&mut self.socket)?;
+        }
+        self.socket.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::login_reply;
+    use crate::client::{
+        ErrorReply, Job, JobAssignment, MessageHandler, Pool, PoolClient, RequestId, Result,
+    };
+
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+    /// Serve one TLS login with a fresh self-signed certificate. Returns the pool address, the
+    /// certificate fingerprint, and the server thread, which yields the line sent after the
+    /// login.
+    fn pool() -> (String, [u8; 32], JoinHandle<io::Result<String>>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let der = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+        let pin = fingerprint(&der);
+        let config = ServerConfig::builder_with_provider(Arc::new(provider::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![der], key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("stratum+ssl://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept()?;
+            let session = ServerConnection::new(Arc::new(config)).map_err(invalid_data)?;
+            let mut stream = BufReader::new(StreamOwned::new(session, socket));
+            let mut line = String::new();
+            stream.read_line(&mut line)?;
+            assert!(line.contains(r#""method":"login""#));
+            writeln!(stream.get_mut(), "{}", login_reply("1"))?;
+            stream.get_mut().flush()?;
+            line.clear();
+            stream.read_line(&mut line)?;
+            Ok(line)
+        });
+        (address, pin, server)
+    }
+
+    struct Ignore;
+
+    impl MessageHandler for Ignore {
+        fn job_command(&mut self, _job: Job) {}
+        fn error_reply(&mut self, _id: RequestId, _error: ErrorReply) {}
+        fn status_reply(&mut self, _id: RequestId, _status: String) {}
+        fn job_reply(&mut self, _id: RequestId, _job: Box<JobAssignment>) {}
+    }
+
+    fn connect(address: &str, tls: TlsOptions) -> Result<PoolClient<Ignore>> {
+        let pool = Pool {
+            tls,
+            ..Pool::new(address, "login", "x", None)
+        };
+        PoolClient::connect_pools(vec![pool], "test", |job| {
+            assert_eq!(job.job_id(), "1");
+            Ignore
+        })
+    }
+
+    #[test]
+    fn pinned_certificate() {
+        let (address, pin, server) = pool();
+        let options = TlsOptions {
+            fingerprint: Some(pin),
+            insecure: false,
+        };
+        let client = connect(&address, options).unwrap();
+        client.write_handle().lock().unwrap().keepalive().unwrap();
+        let line = server.join().unwrap().unwrap();
+        assert!(line.contains(r#""method":"keepalived""#));
+    }
+
+    #[test]
+    fn insecure() {
+        let (address, _, server) = pool();
+        let options = TlsOptions {
+            fingerprint: None,
+            insecure: true,
+        };
+        let client = connect(&address, options).unwrap();
+        client.write_handle().lock().unwrap().keepalive().unwrap();
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn untrusted_certificate() {
+        let (address, _, server) = pool();
+        assert!(connect(&address, TlsOptions::default()).is_err());
+        assert!(server.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn wrong_fingerprint() {
+        let (address, _, server) = pool();
+        let options = TlsOptions {
+            fingerprint: Some([0; 32]),
+            insecure: false,
+        };
+        assert!(connect(&address, options).is_err());
+        assert!(server.join().unwrap().is_err());
+    }
+}
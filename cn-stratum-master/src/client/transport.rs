@@ -0,0 +1,222 @@
+// copyright 2017 Kaz Wesley
+
+//! transports the session layer runs over
+//!
+//! The session layer reads on one thread while shares are written from another, so a transport
+//! splits into a read end and a write end.
+
+use super::connection::{Error, Result};
+use super::TlsOptions;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for a pool to accept a connection, including the TLS handshake.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read end of a transport.
+pub trait TransportRead: Read + Send {
+    /// Make reads fail with `WouldBlock` or `TimedOut` after `timeout`; `None` to block.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// Write end of a transport.
+pub trait TransportWrite: Write + Send {
+    /// Close the connection in both directions.
+    fn shutdown(&mut self) -> io::Result<()>;
+}
+
+/// A connection to a pool.
+pub trait Transport: Send {
+    /// Split into ends that can be used from different threads.
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn TransportRead>, Box<dyn TransportWrite>)>;
+}
+
+/// Opens transports to a pool, instead of dialing its address.
+#[derive(Clone)]
+pub struct Connector(Arc<dyn Fn() -> io::Result<Box<dyn Transport>> + Send + Sync>);
+
+impl Connector {
+    pub fn new<F>(connect: F) -> Self
+    where
+        F: Fn() -> io::Result<Box<dyn Transport>> + Send + Sync + 'static,
+    {
+        Connector(Arc::new(connect))
+    }
+
+    pub fn connect(&self) -> io::Result<Box<dyn Transport>> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Connector")
+    }
+}
+
+impl TransportRead for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl TransportWrite for TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl Transport for TcpStream {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn TransportRead>, Box<dyn TransportWrite>)> {
+        Ok((Box::new(self.try_clone()?), self))
+    }
+}
+
+#[cfg(unix)]
+impl TransportRead for UnixStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl TransportWrite for UnixStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn split(self: Box<Self>) -> io::Result<(Box<dyn TransportRead>, Box<dyn TransportWrite>)> {
+        Ok((Box::new(self.try_clone()?), self))
+    }
+}
+
+/// How to reach a pool, from its address.
+#[derive(Debug, PartialEq)]
+enum Endpoint<'a> {
+    Tcp(&'a str),
+    Tls(&'a str),
+    Unix(&'a str),
+}
+
+/// Parse a pool address: `host:port` or `stratum+tcp://host:port` for plain TCP,
+/// `stratum+ssl://host:port` (or `stratum+tls://`) for TLS, and `unix:///path` for a Unix domain
+/// socket, e.g. of a local proxy.
+fn parse_address(address: &str) -> Result<Endpoint<'_>> {
+    match address.split_once("://") {
+        None => Ok(Endpoint::Tcp(address)),
+        Some(("stratum+tcp", rest)) => Ok(Endpoint::Tcp(rest)),
+        Some(("stratum+ssl", rest)) | Some(("stratum+tls", rest)) => Ok(Endpoint::Tls(rest)),
+        Some(("unix", path)) => Ok(Endpoint::Unix(path)),
+        Some((scheme, _)) => Err(Error::unsupported_scheme(scheme)),
+    }
+}
+
+/// Host name or IP address of `host:port` or `[ipv6]:port`.
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+fn host(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Connect to the first address `address` resolves to that accepts within CONNECT_TIMEOUT.
+fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing")))
+}
+
+/// Open a transport to the pool at `address`.
+pub fn dial(address: &str, tls: &TlsOptions) -> Result<Box<dyn Transport>> {
+    match parse_address(address)? {
+        Endpoint::Tcp(address) => Ok(Box::new(connect_tcp(address)?)),
+        #[cfg(feature = "tls")]
+        Endpoint::Tls(address) => {
+            let socket = connect_tcp(address)?;
+            socket.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+            let stream = super::tls::TlsStream::connect(socket, host(address), tls)?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(not(feature = "tls"))]
+        Endpoint::Tls(_) => {
+            let _ = tls;
+            Err(Error::unsupported_scheme(
+                "stratum+ssl (built without the tls feature)",
+            ))
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => Err(Error::unsupported_scheme("unix")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_scheme() {
+        assert_eq!(
+            parse_address("pool:3333").unwrap(),
+            Endpoint::Tcp("pool:3333")
+        );
+        assert_eq!(
+            parse_address("stratum+tcp://pool:3333").unwrap(),
+            Endpoint::Tcp("pool:3333")
+        );
+        assert_eq!(
+            parse_address("stratum+ssl://pool:443").unwrap(),
+            Endpoint::Tls("pool:443")
+        );
+        assert_eq!(
+            parse_address("unix:///run/proxy.sock").unwrap(),
+            Endpoint::Unix("/run/proxy.sock")
+        );
+        assert!(parse_address("http://pool:80").is_err());
+        assert_eq!(host("pool:443"), "pool");
+        assert_eq!(host("[::1]:443"), "::1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("cn-stratum-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let address = format!("unix://{}", path.display());
+        let transport = dial(&address, &TlsOptions::default()).unwrap();
+        let (mut reader, mut writer) = transport.split().unwrap();
+        let (server, _) = listener.accept().unwrap();
+        writeln!(writer, "ping").unwrap();
+        let mut line = String::new();
+        BufReader::new(&server).read_line(&mut line).unwrap();
+        assert_eq!(line, "ping\n");
+        reader
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let err = reader.read(&mut [0; 1]).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
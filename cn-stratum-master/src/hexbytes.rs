@@ -125,6 +125,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_derive::Deserialize;
     use serde_json;
 
     #[derive(Deserialize)]
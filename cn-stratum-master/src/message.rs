@@ -50,6 +50,8 @@ pub struct Job {
     height: u64,
     seed_hash: String, // TODO: unhexlify on deserialize
     #[serde(default)]
+    next_seed_hash: Option<String>,
+    #[serde(default)]
     algo: Option<String>,
     #[serde(default)]
     variant: u32, // xmrig proxy sends this for compat with obsolete xmrig
@@ -69,10 +71,20 @@ impl Job {
         self.job_id
     }
 
+    /// Server-defined identifier of this job
+    pub fn job_id(&self) -> &str {
+        &self.job_id.0
+    }
+
     pub fn seed_hash(&self) -> String {
         self.seed_hash.to_owned()
     }
 
+    /// Seed hash of the next RandomX epoch, sent by some pools ahead of the switch
+    pub fn next_seed_hash(&self) -> Option<&str> {
+        self.next_seed_hash.as_ref().map(|x| x.as_ref())
+    }
+
     pub fn height(&self) -> u64 {
         self.height
     }
@@ -137,7 +149,7 @@ pub struct JobAssignment {
     #[serde(default)]
     status: Option<String>,
     #[serde(default)]
-    extensions: Vec<String>,
+    extensions: Extensions,
 }
 
 impl JobAssignment {
@@ -154,8 +166,36 @@ impl JobAssignment {
         self.status.as_ref().map(|x| x.as_ref())
     }
     /// Protocol extensions supported by the server
-    pub fn extensions(&self) -> impl Iterator<Item = &str> {
-        self.extensions.iter().map(|x| x.as_ref())
+    pub fn extensions(&self) -> Extensions {
+        self.extensions
+    }
+}
+
+/// Protocol extensions a pool supports, from the names listed in its login reply. Names this
+/// client does not know are ignored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(from = "Vec<String>")]
+pub struct Extensions {
+    /// `algo`: the pool picks from the algorithms sent with the login, and names it in each job.
+    pub algo: bool,
+    /// `nicehash`: the pool reserves the top byte of the nonce.
+    pub nicehash: bool,
+    /// `keepalive`: the pool accepts `keepalived` requests.
+    pub keepalive: bool,
+}
+
+impl From<Vec<String>> for Extensions {
+    fn from(names: Vec<String>) -> Self {
+        let mut extensions = Extensions::default();
+        for name in names {
+            match name.as_ref() {
+                "algo" => extensions.algo = true,
+                "nicehash" => extensions.nicehash = true,
+                "keepalive" => extensions.keepalive = true,
+                _ => (),
+            }
+        }
+        extensions
     }
 }
 
@@ -19,16 +19,190 @@
 // SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+#[cfg(feature = "git")]
 extern crate git2;
+#[cfg(feature = "system")]
+extern crate pkg_config;
 
+#[cfg(feature = "git")]
 use git2::{Cred, Oid, Repository};
 use std::env;
-use std::fs::create_dir_all;
+use std::fs::{self, create_dir_all};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// Where the RandomX library comes from, in order of preference. Each is enabled by the cargo
+/// feature of the same name; the first one that is available is used.
+const SOURCES: &[&str] = &["system", "vendored", "git"];
+
+/// A RandomX fork, built from the same sources with a patched `configuration.h`.
+struct Variant {
+    /// Cargo feature that builds it
+    feature: &'static str,
+    /// Library name; every symbol in it is prefixed with the name and an underscore, so it can be
+    /// linked next to rx/0 (see `bindings::prefixed_api`)
+    lib: &'static str,
+    /// `#define`s in `configuration.h` that differ from rx/0
+    defines: &'static [(&'static str, &'static str)],
+}
+
+const VARIANTS: &[Variant] = &[
+    Variant {
+        feature: "rx-wow",
+        lib: "randomx_wow",
+        defines: &[
+            ("RANDOMX_ARGON_SALT", r#""RandomWOW\x01""#),
+            ("RANDOMX_PROGRAM_ITERATIONS", "1024"),
+            ("RANDOMX_PROGRAM_COUNT", "16"),
+            ("RANDOMX_SCRATCHPAD_L3", "1048576"),
+            ("RANDOMX_SCRATCHPAD_L2", "131072"),
+            ("RANDOMX_FREQ_IADD_RS", "25"),
+            ("RANDOMX_FREQ_IROR_R", "10"),
+            ("RANDOMX_FREQ_IROL_R", "0"),
+            ("RANDOMX_FREQ_FSWAP_R", "8"),
+            ("RANDOMX_FREQ_FADD_R", "20"),
+            ("RANDOMX_FREQ_FSUB_R", "20"),
+            ("RANDOMX_FREQ_FMUL_R", "20"),
+            ("RANDOMX_FREQ_CBRANCH", "16"),
+        ],
+    },
+    Variant {
+        feature: "rx-arq",
+        lib: "randomx_arq",
+        defines: &[
+            ("RANDOMX_ARGON_ITERATIONS", "1"),
+            ("RANDOMX_ARGON_SALT", r#""RandomARQ\x01""#),
+            ("RANDOMX_PROGRAM_ITERATIONS", "1024"),
+            ("RANDOMX_PROGRAM_COUNT", "4"),
+            ("RANDOMX_SCRATCHPAD_L3", "262144"),
+            ("RANDOMX_SCRATCHPAD_L2", "131072"),
+        ],
+    },
+    Variant {
+        feature: "rx-sfx",
+        lib: "randomx_sfx",
+        defines: &[("RANDOMX_ARGON_SALT", r#""RandomSFX\x01""#)],
+    },
+];
+
 fn main() {
+    println!("cargo:rerun-if-env-changed=RANDOMX_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=RANDOMX_SRC_DIR");
+
+    let target = env::var("TARGET").unwrap();
+    let mut tried = Vec::new();
+    let linked = SOURCES.iter().find_map(|source| {
+        if !feature_enabled(source) {
+            return None;
+        }
+        let result = match *source {
+            "system" => link_system().map(|()| None),
+            "vendored" => build_vendored(&target).map(Some),
+            _ => build_git(&target).map(Some),
+        };
+        match result {
+            Ok(src_dir) => Some(src_dir),
+            Err(e) => {
+                tried.push(format!("{}: {}", source, e));
+                None
+            }
+        }
+    });
+    let src_dir = match linked {
+        Some(src_dir) => src_dir,
+        None => panic!(
+            "no RandomX library available; enable one of the `system`, `vendored` or `git` \
+             features of randomx-rs and provide its source.\n{}",
+            tried.join("\n")
+        ),
+    };
+
+    let variants: Vec<&Variant> = VARIANTS
+        .iter()
+        .filter(|variant| feature_enabled(variant.feature))
+        .collect();
+    if !variants.is_empty() {
+        if !target.contains("linux") {
+            panic!("the rx-wow, rx-arq and rx-sfx features are only supported on Linux");
+        }
+        let src_dir = src_dir.unwrap_or_else(|| {
+            panic!(
+                "the rx-wow, rx-arq and rx-sfx features build RandomX from source, which needs \
+                 the `vendored` or `git` feature rather than a system library"
+            )
+        });
+        for variant in variants {
+            build_variant(&src_dir, variant);
+        }
+    }
+
+    if target.contains("apple") {
+        println!("cargo:rustc-link-lib=dylib=c++");
+    } else if target.contains("linux") {
+        println!("cargo:rustc-link-lib=dylib=stdc++");
+    } else if target.contains("windows") {
+        //println!("cargo:rustc-link-lib=dylib=c++");
+    } else {
+        unimplemented!();
+    }
+}
+
+fn feature_enabled(feature: &str) -> bool {
+    let var = format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"));
+    env::var_os(var).is_some()
+}
+
+/// Link a system-installed librandomx from `RANDOMX_LIB_DIR`, or else as found by pkg-config.
+#[cfg(feature = "system")]
+fn link_system() -> Result<(), String> {
+    if let Some(dir) = env::var_os("RANDOMX_LIB_DIR") {
+        let dir = PathBuf::from(dir);
+        if !dir.is_dir() {
+            return Err(format!(
+                "RANDOMX_LIB_DIR {} is not a directory",
+                dir.display()
+            ));
+        }
+        println!("cargo:rustc-link-search=native={}", dir.display());
+        println!("cargo:rustc-link-lib=randomx");
+        return Ok(());
+    }
+    pkg_config::Config::new()
+        .probe("randomx")
+        .map(|_| ())
+        .map_err(|e| format!("RANDOMX_LIB_DIR is not set and pkg-config failed: {}", e))
+}
+
+#[cfg(not(feature = "system"))]
+fn link_system() -> Result<(), String> {
+    unreachable!()
+}
+
+/// Build the RandomX source tree of the `vendor/RandomX` submodule, which is pinned to the same
+/// commit as `build_git`, or the one in `RANDOMX_SRC_DIR`, and return its path.
+fn build_vendored(target: &str) -> Result<PathBuf, String> {
+    let src_dir = match env::var_os("RANDOMX_SRC_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("vendor/RandomX"),
+    };
+    if !src_dir.join("CMakeLists.txt").exists() {
+        return Err(format!(
+            "no RandomX source tree (CMakeLists.txt) in {}; run `git submodule update --init`",
+            src_dir.display()
+        ));
+    }
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+
+    let build_dir = Path::new(&env::var("OUT_DIR").unwrap()).join("randomx-build");
+    create_dir_all(&build_dir).unwrap();
+    cmake_build(&src_dir, &build_dir, target);
+    Ok(src_dir)
+}
+
+/// Clone tevador/RandomX at the pinned commit and build it, and return the path of the checkout.
+#[cfg(feature = "git")]
+fn build_git(target: &str) -> Result<PathBuf, String> {
     const RANDOMX_COMMIT: &str = "ac574e3743b00680445994cbe2c38ba0f52db70d";
 
     let out_dir = env::var("OUT_DIR").unwrap();
@@ -44,6 +218,14 @@ fn main() {
             Ok(v) if &v == "true" => build_using_ssh(&repo_dir),
             _ => build_using_https(&repo_dir),
         };
+        let repo = match repo {
+            Ok(repo) => repo,
+            Err(e) => {
+                // don't leave an empty checkout behind for the next build to trip over
+                let _ = std::fs::remove_dir_all(&repo_dir);
+                return Err(e);
+            }
+        };
 
         let oid = Oid::from_str(RANDOMX_COMMIT).unwrap();
         let commit = repo.find_commit(oid).unwrap();
@@ -60,13 +242,23 @@ fn main() {
             .unwrap();
     }
 
-    env::set_current_dir(Path::new(&repo_dir)).unwrap(); //change current path to repo for dependency build
-    let target = env::var("TARGET").unwrap();
+    cmake_build(&repo_dir, &repo_dir, target);
+    Ok(repo_dir)
+}
+
+#[cfg(not(feature = "git"))]
+fn build_git(_target: &str) -> Result<PathBuf, String> {
+    unreachable!()
+}
+
+/// Configure and build RandomX from `src_dir` in `build_dir`, and link the resulting library.
+fn cmake_build(src_dir: &Path, build_dir: &Path, target: &str) {
     if target.contains("windows") {
         let c = Command::new("cmake")
+            .current_dir(build_dir)
             .arg("-G")
             .arg("Visual Studio 16 2019")
-            .arg(".")
+            .arg(src_dir)
             .output()
             .expect("failed to execute CMake");
         println!("status: {}", c.status);
@@ -75,6 +267,7 @@ fn main() {
         assert!(c.status.success());
 
         let m = Command::new("cmake")
+            .current_dir(build_dir)
             .arg("--build")
             .arg(".")
             .arg("--config")
@@ -86,8 +279,11 @@ fn main() {
         std::io::stderr().write_all(&m.stderr).unwrap();
         assert!(m.status.success());
     } else {
+        // single-config generators leave the build type empty, which builds without optimization
         let c = Command::new("cmake")
-            .arg(".")
+            .current_dir(build_dir)
+            .arg("-DCMAKE_BUILD_TYPE=Release")
+            .arg(src_dir)
             .output()
             .expect("failed to execute CMake");
         println!("status: {}", c.status);
@@ -95,6 +291,7 @@ fn main() {
         std::io::stderr().write_all(&c.stderr).unwrap();
         assert!(c.status.success());
         let m = Command::new("make")
+            .current_dir(build_dir)
             .output()
             .expect("failed to execute Make");
         println!("status: {}", m.status);
@@ -103,10 +300,8 @@ fn main() {
         assert!(m.status.success());
     }
 
-    env::set_current_dir(Path::new(&project_dir)).unwrap(); //change path back to main project
-
     if target.contains("windows") {
-        let include = &repo_dir.join("Release");
+        let include = &build_dir.join("Release");
         println!(
             "cargo:rustc-link-search=native={}",
             &include.to_str().unwrap()
@@ -115,23 +310,104 @@ fn main() {
     } else {
         println!(
             "cargo:rustc-link-search=native={}",
-            &repo_dir.to_str().unwrap()
+            &build_dir.to_str().unwrap()
         );
         println!("cargo:rustc-link-lib=randomx");
     } //link to RandomX
+}
 
-    if target.contains("apple") {
-        println!("cargo:rustc-link-lib=dylib=c++");
-    } else if target.contains("linux") {
-        println!("cargo:rustc-link-lib=dylib=stdc++");
-    } else if target.contains("windows") {
-        //println!("cargo:rustc-link-lib=dylib=c++");
-    } else {
-        unimplemented!();
+/// Build `variant` from a copy of the RandomX sources in `src_dir`, prefix its symbols, and link
+/// it statically.
+fn build_variant(src_dir: &Path, variant: &Variant) {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let variant_src = out_dir.join(format!("{}-src", variant.lib));
+    let build_dir = out_dir.join(format!("{}-build", variant.lib));
+    copy_dir(&src_dir.join("src"), &variant_src.join("src"));
+    fs::copy(
+        src_dir.join("CMakeLists.txt"),
+        variant_src.join("CMakeLists.txt"),
+    )
+    .unwrap();
+    patch_configuration(&variant_src.join("src/configuration.h"), variant.defines);
+
+    create_dir_all(&build_dir).unwrap();
+    run(Command::new("cmake")
+        .current_dir(&build_dir)
+        .arg("-DCMAKE_BUILD_TYPE=Release")
+        .arg(&variant_src));
+    run(Command::new("make").current_dir(&build_dir).arg("randomx"));
+
+    // rename every symbol the library defines, along with the references to them inside it
+    let nm = Command::new("nm")
+        .arg("-g")
+        .arg("--defined-only")
+        .arg("--format=posix")
+        .arg(build_dir.join("librandomx.a"))
+        .output()
+        .expect("failed to execute nm");
+    assert!(nm.status.success(), "nm failed");
+    let mut renames = String::new();
+    for line in String::from_utf8(nm.stdout).unwrap().lines() {
+        // archive members are listed as `librandomx.a[member.o]:`
+        match line.split_whitespace().next() {
+            Some(symbol) if !symbol.ends_with(':') => {
+                renames.push_str(&format!("{} {}_{}\n", symbol, variant.lib, symbol))
+            }
+            _ => {}
+        }
+    }
+    let renames_file = build_dir.join("redefine-syms.txt");
+    fs::write(&renames_file, renames).unwrap();
+    run(Command::new("objcopy")
+        .arg(format!("--redefine-syms={}", renames_file.display()))
+        .arg(build_dir.join("librandomx.a"))
+        .arg(build_dir.join(format!("lib{}.a", variant.lib))));
+
+    println!("cargo:rustc-link-search=native={}", build_dir.display());
+    println!("cargo:rustc-link-lib=static={}", variant.lib);
+}
+
+/// Replace the value of each of `defines` in the RandomX `configuration.h` at `path`.
+fn patch_configuration(path: &Path, defines: &[(&str, &str)]) {
+    let mut config = fs::read_to_string(path).unwrap();
+    for (name, value) in defines {
+        let prefix = format!("#define {} ", name);
+        let start = config
+            .find(&prefix)
+            .unwrap_or_else(|| panic!("{} not found in {}", name, path.display()));
+        let end = config[start..]
+            .find('\n')
+            .map_or(config.len(), |i| start + i);
+        config.replace_range(start..end, &format!("{}{}", prefix, value));
+    }
+    fs::write(path, config).unwrap();
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let path = entry.path();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&path, &to.join(entry.file_name()));
+        } else {
+            fs::copy(&path, to.join(entry.file_name())).unwrap();
+        }
     }
 }
 
-fn build_using_ssh(path: &Path) -> Repository {
+fn run(command: &mut Command) {
+    let output = command
+        .output()
+        .unwrap_or_else(|e| panic!("failed to execute {:?}: {}", command, e));
+    println!("status: {}", output.status);
+    std::io::stdout().write_all(&output.stdout).unwrap();
+    std::io::stderr().write_all(&output.stderr).unwrap();
+    assert!(output.status.success(), "{:?} failed", command);
+}
+
+#[cfg(feature = "git")]
+fn build_using_ssh(path: &Path) -> Result<Repository, String> {
     let url = "ssh://git@github.com/tevador/RandomX.git";
     // Build up auth credentials via fetch options:
     let mut cb = git2::RemoteCallbacks::new();
@@ -144,16 +420,13 @@ fn build_using_ssh(path: &Path) -> Repository {
 
     let mut builder = git2::build::RepoBuilder::new();
     builder.fetch_options(fo);
-    match builder.clone(url, &path) {
-        Ok(repo) => repo,
-        Err(e) => panic!("Failed to clone RandomX: {}", e),
-    }
+    builder
+        .clone(url, &path)
+        .map_err(|e| format!("Failed to clone RandomX: {}", e))
 }
 
-fn build_using_https(path: &Path) -> Repository {
+#[cfg(feature = "git")]
+fn build_using_https(path: &Path) -> Result<Repository, String> {
     let url = "https://github.com/tevador/RandomX.git";
-    match Repository::clone(url, &path) {
-        Ok(repo) => repo,
-        Err(e) => panic!("Failed to clone RandomX: {}", e),
-    }
+    Repository::clone(url, &path).map_err(|e| format!("Failed to clone RandomX: {}", e))
 }
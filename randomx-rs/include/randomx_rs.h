@@ -0,0 +1,229 @@
+#ifndef RANDOMX_RS_H
+#define RANDOMX_RS_H
+
+/* Generated from src/capi.rs by cbindgen, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Version of the C API, increased on every incompatible change.
+ */
+#define RANDOMX_RS_API_VERSION 1
+
+#define RANDOMX_RS_FLAG_DEFAULT 0
+
+#define RANDOMX_RS_FLAG_LARGE_PAGES 1
+
+#define RANDOMX_RS_FLAG_HARD_AES 2
+
+#define RANDOMX_RS_FLAG_FULL_MEM 4
+
+#define RANDOMX_RS_FLAG_JIT 8
+
+#define RANDOMX_RS_FLAG_SECURE 16
+
+#define RANDOMX_RS_FLAG_ARGON2_SSSE3 32
+
+#define RANDOMX_RS_FLAG_ARGON2_AVX2 64
+
+/**
+ * Size of a RandomX hash in bytes.
+ */
+#define RANDOMX_RS_HASH_SIZE 32
+
+/**
+ * Result of a call, see `randomx_rs_last_error`.
+ */
+typedef enum randomx_rs_error {
+  RANDOMX_RS_ERROR_OK = 0,
+  RANDOMX_RS_ERROR_ALLOCATION_FAILED,
+  RANDOMX_RS_ERROR_LARGE_PAGES_FAILED,
+  RANDOMX_RS_ERROR_INVALID_FLAGS,
+  RANDOMX_RS_ERROR_EMPTY_KEY,
+  RANDOMX_RS_ERROR_EMPTY_INPUT,
+  RANDOMX_RS_ERROR_INVALID_PARAMETER,
+  RANDOMX_RS_ERROR_SELF_TEST_FAILED,
+  RANDOMX_RS_ERROR_DATASET_INCOMPLETE,
+  RANDOMX_RS_ERROR_UNSUPPORTED_ALGORITHM,
+  /**
+   * A required pointer argument was `NULL`
+   */
+  RANDOMX_RS_ERROR_NULL_POINTER,
+  /**
+   * The call panicked; the library is still usable, but the object it was called on may not be
+   */
+  RANDOMX_RS_ERROR_PANIC,
+  RANDOMX_RS_ERROR_OTHER,
+} randomx_rs_error;
+
+/**
+ * A RandomX cache.
+ */
+typedef struct randomx_rs_cache randomx_rs_cache;
+
+/**
+ * A RandomX dataset.
+ */
+typedef struct randomx_rs_dataset randomx_rs_dataset;
+
+/**
+ * A thread-safe hash verifier, see `Verifier`.
+ */
+typedef struct randomx_rs_verifier randomx_rs_verifier;
+
+/**
+ * A RandomX VM. It can be used from one thread at a time.
+ */
+typedef struct randomx_rs_vm randomx_rs_vm;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns `RANDOMX_RS_API_VERSION` of the library, to check it against the header.
+ */
+uint32_t randomx_rs_api_version(void);
+
+/**
+ * Returns the error code of the last call on this thread, `RANDOMX_RS_ERROR_OK` if it succeeded.
+ */
+enum randomx_rs_error randomx_rs_last_error(void);
+
+/**
+ * Returns a static, NUL-terminated description of `code`, a `randomx_rs_error`, or of an unknown
+ * error if it is none.
+ */
+const char *randomx_rs_error_message(int code);
+
+/**
+ * Returns the flags recommended for this CPU, see `RandomXFlag::get_recommended_flags`.
+ */
+uint32_t randomx_rs_recommended_flags(void);
+
+/**
+ * Creates a cache for `algorithm` (e.g. `"rx/0"`, or `NULL` for rx/0) initialized with `key`.
+ * Returns `NULL` on failure.
+ *
+ * # Safety
+ * `algorithm` is `NULL` or a NUL-terminated string, and `key` points to `key_len` bytes.
+ */
+struct randomx_rs_cache *randomx_rs_cache_new(const char *algorithm,
+                                              uint32_t flags,
+                                              const uint8_t *key,
+                                              size_t key_len);
+
+/**
+ * Destroys a cache. Datasets and VMs created from it keep working.
+ *
+ * # Safety
+ * `cache` is `NULL` or was returned by `randomx_rs_cache_new` and not destroyed yet.
+ */
+void randomx_rs_cache_free(struct randomx_rs_cache *cache);
+
+/**
+ * Creates a dataset from `cache`, initialized on `threads` threads. Returns `NULL` on failure.
+ *
+ * # Safety
+ * `cache` was returned by `randomx_rs_cache_new`.
+ */
+struct randomx_rs_dataset *randomx_rs_dataset_new(uint32_t flags,
+                                                  const struct randomx_rs_cache *cache,
+                                                  size_t threads);
+
+/**
+ * Destroys a dataset. VMs created with it keep working.
+ *
+ * # Safety
+ * `dataset` is `NULL` or was returned by `randomx_rs_dataset_new` and not destroyed yet.
+ */
+void randomx_rs_dataset_free(struct randomx_rs_dataset *dataset);
+
+/**
+ * Creates a VM over `cache` (light mode) or, with `RANDOMX_RS_FLAG_FULL_MEM`, over `dataset`.
+ * Either may be `NULL` if the flags do not need it. Returns `NULL` on failure.
+ *
+ * # Safety
+ * `cache` and `dataset` are `NULL` or were returned by `randomx_rs_cache_new` and
+ * `randomx_rs_dataset_new`.
+ */
+struct randomx_rs_vm *randomx_rs_vm_new(uint32_t flags,
+                                        const struct randomx_rs_cache *cache,
+                                        const struct randomx_rs_dataset *dataset);
+
+/**
+ * Destroys a VM.
+ *
+ * # Safety
+ * `vm` is `NULL` or was returned by `randomx_rs_vm_new` and not destroyed yet.
+ */
+void randomx_rs_vm_free(struct randomx_rs_vm *vm);
+
+/**
+ * Writes the hash of `input` to the `RANDOMX_RS_HASH_SIZE` bytes at `output`.
+ *
+ * # Safety
+ * `vm` was returned by `randomx_rs_vm_new` and is not in use on another thread, `input` points to
+ * `input_len` bytes, and `output` to `RANDOMX_RS_HASH_SIZE` writable bytes.
+ */
+enum randomx_rs_error randomx_rs_vm_hash(struct randomx_rs_vm *vm,
+                                         const uint8_t *input,
+                                         size_t input_len,
+                                         uint8_t *output);
+
+/**
+ * Returns whether the hash of `input` is the `RANDOMX_RS_HASH_SIZE` bytes at `expected`, and
+ * `false` on failure.
+ *
+ * # Safety
+ * As for `randomx_rs_vm_hash`, with `expected` pointing to `RANDOMX_RS_HASH_SIZE` bytes.
+ */
+bool randomx_rs_vm_verify(struct randomx_rs_vm *vm,
+                          const uint8_t *input,
+                          size_t input_len,
+                          const uint8_t *expected);
+
+/**
+ * Creates a verifier for `algorithm` (`NULL` for rx/0), keeping light-mode caches for up to
+ * `capacity` seeds. It can be used from any number of threads at once. Returns `NULL` on failure.
+ *
+ * # Safety
+ * `algorithm` is `NULL` or a NUL-terminated string.
+ */
+struct randomx_rs_verifier *randomx_rs_verifier_new(const char *algorithm,
+                                                    uint32_t flags,
+                                                    size_t capacity);
+
+/**
+ * Destroys a verifier.
+ *
+ * # Safety
+ * `verifier` is `NULL` or was returned by `randomx_rs_verifier_new` and not destroyed yet, and no
+ * other thread is using it.
+ */
+void randomx_rs_verifier_free(struct randomx_rs_verifier *verifier);
+
+/**
+ * Returns whether the hash of `input` with the cache for `seed` is the `RANDOMX_RS_HASH_SIZE`
+ * bytes at `expected`, and `false` on failure.
+ *
+ * # Safety
+ * `verifier` was returned by `randomx_rs_verifier_new`, `seed` and `input` point to `seed_len`
+ * and `input_len` bytes, and `expected` to `RANDOMX_RS_HASH_SIZE` bytes.
+ */
+bool randomx_rs_verifier_verify(const struct randomx_rs_verifier *verifier,
+                                const uint8_t *seed,
+                                size_t seed_len,
+                                const uint8_t *input,
+                                size_t input_len,
+                                const uint8_t *expected);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* RANDOMX_RS_H */
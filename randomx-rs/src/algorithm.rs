@@ -0,0 +1,125 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::bindings::{Api, RX0};
+use std::fmt;
+
+/// A RandomX variant. Monero's rx/0 is always available; the others are built into the library
+/// by the cargo feature of the same name (`rx-wow`, `rx-arq` and `rx-sfx`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// Monero
+    #[default]
+    Rx0,
+    /// Wownero: its own Argon2 salt, 16 programs of 1024 iterations, a 1 MiB scratchpad and
+    /// different instruction frequencies
+    RxWow,
+    /// ArQmA: its own Argon2 salt, 1 Argon2 iteration, 4 programs of 1024 iterations and a
+    /// 256 KiB scratchpad
+    RxArq,
+    /// Safex: its own Argon2 salt
+    RxSfx,
+}
+
+impl Algorithm {
+    /// Every algorithm, whether it is available or not.
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::Rx0,
+        Algorithm::RxWow,
+        Algorithm::RxArq,
+        Algorithm::RxSfx,
+    ];
+
+    /// Name of the algorithm in the stratum protocol, e.g. `rx/0`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Rx0 => "rx/0",
+            Algorithm::RxWow => "rx/wow",
+            Algorithm::RxArq => "rx/arq",
+            Algorithm::RxSfx => "rx/sfx",
+        }
+    }
+
+    /// Returns the algorithm with the stratum name `name`, also accepting `randomx` and
+    /// `rx/monero` for rx/0.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name {
+            "randomx" | "rx/monero" => Some(Algorithm::Rx0),
+            _ => Algorithm::ALL.iter().copied().find(|a| a.name() == name),
+        }
+    }
+
+    /// Returns whether the library was built with this algorithm.
+    pub fn is_available(self) -> bool {
+        self.api().is_some()
+    }
+
+    /// Returns the algorithms the library was built with.
+    pub fn available() -> Vec<Algorithm> {
+        Algorithm::ALL
+            .iter()
+            .copied()
+            .filter(|a| a.is_available())
+            .collect()
+    }
+
+    pub(crate) fn api(self) -> Option<&'static Api> {
+        match self {
+            Algorithm::Rx0 => Some(&RX0),
+            #[cfg(feature = "rx-wow")]
+            Algorithm::RxWow => Some(&crate::bindings::wow::API),
+            #[cfg(feature = "rx-arq")]
+            Algorithm::RxArq => Some(&crate::bindings::arq::API),
+            #[cfg(feature = "rx-sfx")]
+            Algorithm::RxSfx => Some(&crate::bindings::sfx::API),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// The functions of an algorithm that objects were already created for.
+    pub(crate) fn ffi(self) -> &'static Api {
+        self.api().expect("algorithm is not built into the library")
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Algorithm;
+
+    #[test]
+    fn algorithm_names() {
+        for algorithm in Algorithm::ALL.iter() {
+            assert_eq!(Algorithm::from_name(algorithm.name()), Some(*algorithm));
+        }
+        assert_eq!(Algorithm::from_name("randomx"), Some(Algorithm::Rx0));
+        assert_eq!(Algorithm::from_name("cn/r"), None);
+        assert!(Algorithm::Rx0.is_available());
+        assert_eq!(Algorithm::available()[0], Algorithm::Rx0);
+    }
+}
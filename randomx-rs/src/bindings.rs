@@ -86,6 +86,173 @@ extern "C" {
     pub fn randomx_get_flags() -> c_uint;
 }
 
+/// The RandomX functions of one algorithm. Each algorithm is a separate build of RandomX.
+pub struct Api {
+    pub alloc_cache: unsafe extern "C" fn(flags: c_uint) -> *mut randomx_cache,
+    pub init_cache:
+        unsafe extern "C" fn(cache: *mut randomx_cache, key: *const c_void, key_size: usize),
+    pub release_cache: unsafe extern "C" fn(cache: *mut randomx_cache),
+    pub alloc_dataset: unsafe extern "C" fn(flags: c_uint) -> *mut randomx_dataset,
+    pub dataset_item_count: unsafe extern "C" fn() -> c_ulong,
+    pub init_dataset: unsafe extern "C" fn(
+        dataset: *mut randomx_dataset,
+        cache: *mut randomx_cache,
+        start_item: c_ulong,
+        item_count: c_ulong,
+    ),
+    pub get_dataset_memory: unsafe extern "C" fn(dataset: *mut randomx_dataset) -> *mut c_void,
+    pub release_dataset: unsafe extern "C" fn(dataset: *mut randomx_dataset),
+    pub create_vm: unsafe extern "C" fn(
+        flags: c_uint,
+        cache: *mut randomx_cache,
+        dataset: *mut randomx_dataset,
+    ) -> *mut randomx_vm,
+    pub vm_set_cache: unsafe extern "C" fn(machine: *mut randomx_vm, cache: *mut randomx_cache),
+    pub vm_set_dataset:
+        unsafe extern "C" fn(machine: *mut randomx_vm, dataset: *mut randomx_dataset),
+    pub destroy_vm: unsafe extern "C" fn(machine: *mut randomx_vm),
+    pub calculate_hash: unsafe extern "C" fn(
+        machine: *mut randomx_vm,
+        input: *const c_void,
+        input_size: usize,
+        output: *mut c_void,
+    ),
+    pub calculate_hash_first:
+        unsafe extern "C" fn(machine: *mut randomx_vm, input: *const c_void, input_size: usize),
+    pub calculate_hash_next: unsafe extern "C" fn(
+        machine: *mut randomx_vm,
+        input_next: *const c_void,
+        input_size_next: usize,
+        output: *mut c_void,
+    ),
+    pub calculate_hash_last: unsafe extern "C" fn(machine: *mut randomx_vm, output: *mut c_void),
+}
+
+/// Monero's rx/0.
+pub static RX0: Api = Api {
+    alloc_cache: randomx_alloc_cache,
+    init_cache: randomx_init_cache,
+    release_cache: randomx_release_cache,
+    alloc_dataset: randomx_alloc_dataset,
+    dataset_item_count: randomx_dataset_item_count,
+    init_dataset: randomx_init_dataset,
+    get_dataset_memory: randomx_get_dataset_memory,
+    release_dataset: randomx_release_dataset,
+    create_vm: randomx_create_vm,
+    vm_set_cache: randomx_vm_set_cache,
+    vm_set_dataset: randomx_vm_set_dataset,
+    destroy_vm: randomx_destroy_vm,
+    calculate_hash: randomx_calculate_hash,
+    calculate_hash_first: randomx_calculate_hash_first,
+    calculate_hash_next: randomx_calculate_hash_next,
+    calculate_hash_last: randomx_calculate_hash_last,
+};
+
+/// Declares `API` for a RandomX variant, whose library has every symbol prefixed with `$prefix`
+/// (see `build.rs`).
+#[allow(unused_macros)]
+macro_rules! prefixed_api {
+    ($prefix:literal) => {
+        use crate::bindings::{randomx_cache, randomx_dataset, randomx_vm, Api};
+        use libc::{c_uint, c_ulong, c_void};
+
+        extern "C" {
+            #[link_name = concat!($prefix, "randomx_alloc_cache")]
+            fn alloc_cache(flags: c_uint) -> *mut randomx_cache;
+            #[link_name = concat!($prefix, "randomx_init_cache")]
+            fn init_cache(cache: *mut randomx_cache, key: *const c_void, keySize: usize);
+            #[link_name = concat!($prefix, "randomx_release_cache")]
+            fn release_cache(cache: *mut randomx_cache);
+            #[link_name = concat!($prefix, "randomx_alloc_dataset")]
+            fn alloc_dataset(flags: c_uint) -> *mut randomx_dataset;
+            #[link_name = concat!($prefix, "randomx_dataset_item_count")]
+            fn dataset_item_count() -> c_ulong;
+            #[link_name = concat!($prefix, "randomx_init_dataset")]
+            fn init_dataset(
+                dataset: *mut randomx_dataset,
+                cache: *mut randomx_cache,
+                start_item: c_ulong,
+                item_count: c_ulong,
+            );
+            #[link_name = concat!($prefix, "randomx_get_dataset_memory")]
+            fn get_dataset_memory(dataset: *mut randomx_dataset) -> *mut c_void;
+            #[link_name = concat!($prefix, "randomx_release_dataset")]
+            fn release_dataset(dataset: *mut randomx_dataset);
+            #[link_name = concat!($prefix, "randomx_create_vm")]
+            fn create_vm(
+                flags: c_uint,
+                cache: *mut randomx_cache,
+                dataset: *mut randomx_dataset,
+            ) -> *mut randomx_vm;
+            #[link_name = concat!($prefix, "randomx_vm_set_cache")]
+            fn vm_set_cache(machine: *mut randomx_vm, cache: *mut randomx_cache);
+            #[link_name = concat!($prefix, "randomx_vm_set_dataset")]
+            fn vm_set_dataset(machine: *mut randomx_vm, dataset: *mut randomx_dataset);
+            #[link_name = concat!($prefix, "randomx_destroy_vm")]
+            fn destroy_vm(machine: *mut randomx_vm);
+            #[link_name = concat!($prefix, "randomx_calculate_hash")]
+            fn calculate_hash(
+                machine: *mut randomx_vm,
+                input: *const c_void,
+                input_size: usize,
+                output: *mut c_void,
+            );
+            #[link_name = concat!($prefix, "randomx_calculate_hash_first")]
+            fn calculate_hash_first(
+                machine: *mut randomx_vm,
+                input: *const c_void,
+                input_size: usize,
+            );
+            #[link_name = concat!($prefix, "randomx_calculate_hash_next")]
+            fn calculate_hash_next(
+                machine: *mut randomx_vm,
+                input_next: *const c_void,
+                input_size_next: usize,
+                output: *mut c_void,
+            );
+            #[link_name = concat!($prefix, "randomx_calculate_hash_last")]
+            fn calculate_hash_last(machine: *mut randomx_vm, output: *mut c_void);
+        }
+
+        pub static API: Api = Api {
+            alloc_cache,
+            init_cache,
+            release_cache,
+            alloc_dataset,
+            dataset_item_count,
+            init_dataset,
+            get_dataset_memory,
+            release_dataset,
+            create_vm,
+            vm_set_cache,
+            vm_set_dataset,
+            destroy_vm,
+            calculate_hash,
+            calculate_hash_first,
+            calculate_hash_next,
+            calculate_hash_last,
+        };
+    };
+}
+
+/// Wownero's rx/wow.
+#[cfg(feature = "rx-wow")]
+pub mod wow {
+    prefixed_api!("randomx_wow_");
+}
+
+/// ArQmA's rx/arq.
+#[cfg(feature = "rx-arq")]
+pub mod arq {
+    prefixed_api!("randomx_arq_");
+}
+
+/// Safex's rx/sfx.
+#[cfg(feature = "rx-sfx")]
+pub mod sfx {
+    prefixed_api!("randomx_sfx_");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,468 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! C API of the `randomx-rs` shared library, declared in `include/randomx_rs.h`.
+//!
+//! Every function is prefixed with `randomx_rs_`, so the library can be loaded next to librandomx.
+//! Objects are created and destroyed in pairs (`..._new` and `..._free`) and are opaque to C. A
+//! function that fails returns `NULL`, `false` or an error code, and `randomx_rs_last_error`
+//! returns the code of the last call on the calling thread.
+//!
+//! The header is generated from this file with cbindgen; a test checks that it is up to date.
+
+use crate::{
+    Algorithm, RandomXCache, RandomXDataset, RandomXError, RandomXFlag, RandomXVM, Verifier,
+};
+use libc::{c_char, c_int};
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// Version of the C API, increased on every incompatible change.
+pub const RANDOMX_RS_API_VERSION: u32 = 1;
+
+pub const RANDOMX_RS_FLAG_DEFAULT: u32 = 0;
+pub const RANDOMX_RS_FLAG_LARGE_PAGES: u32 = 1;
+pub const RANDOMX_RS_FLAG_HARD_AES: u32 = 2;
+pub const RANDOMX_RS_FLAG_FULL_MEM: u32 = 4;
+pub const RANDOMX_RS_FLAG_JIT: u32 = 8;
+pub const RANDOMX_RS_FLAG_SECURE: u32 = 16;
+pub const RANDOMX_RS_FLAG_ARGON2_SSSE3: u32 = 32;
+pub const RANDOMX_RS_FLAG_ARGON2_AVX2: u32 = 64;
+
+/// Size of a RandomX hash in bytes.
+pub const RANDOMX_RS_HASH_SIZE: usize = 32;
+
+/// Result of a call, see `randomx_rs_last_error`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    AllocationFailed,
+    LargePagesFailed,
+    InvalidFlags,
+    EmptyKey,
+    EmptyInput,
+    InvalidParameter,
+    SelfTestFailed,
+    DatasetIncomplete,
+    UnsupportedAlgorithm,
+    /// A required pointer argument was `NULL`
+    NullPointer,
+    /// The call panicked; the library is still usable, but the object it was called on may not be
+    Panic,
+    Other,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 13] = [
+        ErrorCode::Ok,
+        ErrorCode::AllocationFailed,
+        ErrorCode::LargePagesFailed,
+        ErrorCode::InvalidFlags,
+        ErrorCode::EmptyKey,
+        ErrorCode::EmptyInput,
+        ErrorCode::InvalidParameter,
+        ErrorCode::SelfTestFailed,
+        ErrorCode::DatasetIncomplete,
+        ErrorCode::UnsupportedAlgorithm,
+        ErrorCode::NullPointer,
+        ErrorCode::Panic,
+        ErrorCode::Other,
+    ];
+
+    /// The code with the value `code`, `None` if there is none. C may pass any `int` where an
+    /// enum is expected, so codes are taken from C as integers and converted here.
+    fn from_raw(code: c_int) -> Option<ErrorCode> {
+        ErrorCode::ALL.iter().copied().find(|&c| c as c_int == code)
+    }
+}
+
+impl From<RandomXError> for ErrorCode {
+    fn from(e: RandomXError) -> ErrorCode {
+        match e {
+            RandomXError::AllocationFailed { .. } => ErrorCode::AllocationFailed,
+            RandomXError::LargePagesFailed { .. } => ErrorCode::LargePagesFailed,
+            RandomXError::InvalidFlags { .. } => ErrorCode::InvalidFlags,
+            RandomXError::EmptyKey => ErrorCode::EmptyKey,
+            RandomXError::EmptyInput => ErrorCode::EmptyInput,
+            RandomXError::InvalidParameter(_) => ErrorCode::InvalidParameter,
+            RandomXError::SelfTestFailed { .. } => ErrorCode::SelfTestFailed,
+            RandomXError::DatasetIncomplete { .. } => ErrorCode::DatasetIncomplete,
+            RandomXError::UnsupportedAlgorithm(_) => ErrorCode::UnsupportedAlgorithm,
+            RandomXError::Other(_) => ErrorCode::Other,
+        }
+    }
+}
+
+/// A RandomX cache.
+pub struct Cache(RandomXCache);
+
+/// A RandomX dataset.
+pub struct Dataset(RandomXDataset);
+
+/// A RandomX VM. It can be used from one thread at a time.
+pub struct Vm(RandomXVM);
+
+/// A thread-safe hash verifier, see `Verifier`.
+pub struct HashVerifier(Verifier);
+
+thread_local! {
+    static LAST_ERROR: Cell<ErrorCode> = const { Cell::new(ErrorCode::Ok) };
+}
+
+/// Run `f`, record its error code for `randomx_rs_last_error`, and return its value, or `failed`
+/// if it failed or panicked.
+fn call<T, F>(failed: T, f: F) -> T
+where
+    F: FnOnce() -> Result<T, ErrorCode>,
+{
+    let (value, code) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => (value, ErrorCode::Ok),
+        Ok(Err(code)) => (failed, code),
+        Err(_) => (failed, ErrorCode::Panic),
+    };
+    LAST_ERROR.with(|last| last.set(code));
+    value
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], ErrorCode> {
+    if data.is_null() {
+        return if len == 0 {
+            Ok(&[])
+        } else {
+            Err(ErrorCode::NullPointer)
+        };
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+unsafe fn object<'a, T>(object: *const T) -> Result<&'a T, ErrorCode> {
+    object.as_ref().ok_or(ErrorCode::NullPointer)
+}
+
+unsafe fn algorithm(name: *const c_char) -> Result<Algorithm, ErrorCode> {
+    if name.is_null() {
+        return Ok(Algorithm::Rx0);
+    }
+    let name = CStr::from_ptr(name)
+        .to_str()
+        .map_err(|_| ErrorCode::InvalidParameter)?;
+    Algorithm::from_name(name).ok_or(ErrorCode::UnsupportedAlgorithm)
+}
+
+fn flags(bits: u32) -> Result<RandomXFlag, ErrorCode> {
+    RandomXFlag::from_bits(bits).ok_or(ErrorCode::InvalidFlags)
+}
+
+fn new<T>(value: T) -> *mut T {
+    Box::into_raw(Box::new(value))
+}
+
+unsafe fn free<T>(object: *mut T) {
+    if !object.is_null() {
+        drop(Box::from_raw(object));
+    }
+}
+
+/// Returns `RANDOMX_RS_API_VERSION` of the library, to check it against the header.
+#[no_mangle]
+pub extern "C" fn randomx_rs_api_version() -> u32 {
+    RANDOMX_RS_API_VERSION
+}
+
+/// Returns the error code of the last call on this thread, `RANDOMX_RS_ERROR_OK` if it succeeded.
+#[no_mangle]
+pub extern "C" fn randomx_rs_last_error() -> ErrorCode {
+    LAST_ERROR.with(|last| last.get())
+}
+
+/// Returns a static, NUL-terminated description of `code`, a `randomx_rs_error`, or of an unknown
+/// error if it is none.
+#[no_mangle]
+pub extern "C" fn randomx_rs_error_message(code: c_int) -> *const c_char {
+    let message: &'static [u8] = match ErrorCode::from_raw(code) {
+        None => b"unknown error\0",
+        Some(ErrorCode::Ok) => b"no error\0",
+        Some(ErrorCode::AllocationFailed) => b"allocation failed\0",
+        Some(ErrorCode::LargePagesFailed) => b"allocation in large pages failed\0",
+        Some(ErrorCode::InvalidFlags) => b"invalid flags\0",
+        Some(ErrorCode::EmptyKey) => b"key is empty\0",
+        Some(ErrorCode::EmptyInput) => b"input is empty\0",
+        Some(ErrorCode::InvalidParameter) => b"invalid parameter\0",
+        Some(ErrorCode::SelfTestFailed) => b"self-test failed\0",
+        Some(ErrorCode::DatasetIncomplete) => b"dataset initialization incomplete\0",
+        Some(ErrorCode::UnsupportedAlgorithm) => b"algorithm not supported\0",
+        Some(ErrorCode::NullPointer) => b"null pointer\0",
+        Some(ErrorCode::Panic) => b"internal error\0",
+        Some(ErrorCode::Other) => b"RandomX error\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Returns the flags recommended for this CPU, see `RandomXFlag::get_recommended_flags`.
+#[no_mangle]
+pub extern "C" fn randomx_rs_recommended_flags() -> u32 {
+    RandomXFlag::get_recommended_flags().bits()
+}
+
+/// Creates a cache for `algorithm` (e.g. `"rx/0"`, or `NULL` for rx/0) initialized with `key`.
+/// Returns `NULL` on failure.
+///
+/// # Safety
+/// `algorithm` is `NULL` or a NUL-terminated string, and `key` points to `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_cache_new(
+    algorithm: *const c_char,
+    flags: u32,
+    key: *const u8,
+    key_len: usize,
+) -> *mut Cache {
+    call(ptr::null_mut(), || {
+        let algorithm = self::algorithm(algorithm)?;
+        let cache =
+            RandomXCache::with_algorithm(algorithm, self::flags(flags)?, bytes(key, key_len)?)?;
+        Ok(new(Cache(cache)))
+    })
+}
+
+/// Destroys a cache. Datasets and VMs created from it keep working.
+///
+/// # Safety
+/// `cache` is `NULL` or was returned by `randomx_rs_cache_new` and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_cache_free(cache: *mut Cache) {
+    free(cache)
+}
+
+/// Creates a dataset from `cache`, initialized on `threads` threads. Returns `NULL` on failure.
+///
+/// # Safety
+/// `cache` was returned by `randomx_rs_cache_new`.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_dataset_new(
+    flags: u32,
+    cache: *const Cache,
+    threads: usize,
+) -> *mut Dataset {
+    call(ptr::null_mut(), || {
+        let cache = &object(cache)?.0;
+        let dataset =
+            RandomXDataset::new_parallel(self::flags(flags)?, cache, 0, threads.max(1), |_, _| {})?;
+        Ok(new(Dataset(dataset)))
+    })
+}
+
+/// Destroys a dataset. VMs created with it keep working.
+///
+/// # Safety
+/// `dataset` is `NULL` or was returned by `randomx_rs_dataset_new` and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_dataset_free(dataset: *mut Dataset) {
+    free(dataset)
+}
+
+/// Creates a VM over `cache` (light mode) or, with `RANDOMX_RS_FLAG_FULL_MEM`, over `dataset`.
+/// Either may be `NULL` if the flags do not need it. Returns `NULL` on failure.
+///
+/// # Safety
+/// `cache` and `dataset` are `NULL` or were returned by `randomx_rs_cache_new` and
+/// `randomx_rs_dataset_new`.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_vm_new(
+    flags: u32,
+    cache: *const Cache,
+    dataset: *const Dataset,
+) -> *mut Vm {
+    call(ptr::null_mut(), || {
+        let cache = cache.as_ref().map(|cache| &cache.0);
+        let dataset = dataset.as_ref().map(|dataset| &dataset.0);
+        let vm = RandomXVM::new(self::flags(flags)?, cache, dataset)?;
+        Ok(new(Vm(vm)))
+    })
+}
+
+/// Destroys a VM.
+///
+/// # Safety
+/// `vm` is `NULL` or was returned by `randomx_rs_vm_new` and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_vm_free(vm: *mut Vm) {
+    free(vm)
+}
+
+/// Writes the hash of `input` to the `RANDOMX_RS_HASH_SIZE` bytes at `output`.
+///
+/// # Safety
+/// `vm` was returned by `randomx_rs_vm_new` and is not in use on another thread, `input` points to
+/// `input_len` bytes, and `output` to `RANDOMX_RS_HASH_SIZE` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_vm_hash(
+    vm: *mut Vm,
+    input: *const u8,
+    input_len: usize,
+    output: *mut u8,
+) -> ErrorCode {
+    call((), || {
+        let vm = &mut vm.as_mut().ok_or(ErrorCode::NullPointer)?.0;
+        if output.is_null() {
+            return Err(ErrorCode::NullPointer);
+        }
+        let hash = vm.calculate_hash(bytes(input, input_len)?)?;
+        ptr::copy_nonoverlapping(hash.as_ptr(), output, hash.len());
+        Ok(())
+    });
+    randomx_rs_last_error()
+}
+
+/// Returns whether the hash of `input` is the `RANDOMX_RS_HASH_SIZE` bytes at `expected`, and
+/// `false` on failure.
+///
+/// # Safety
+/// As for `randomx_rs_vm_hash`, with `expected` pointing to `RANDOMX_RS_HASH_SIZE` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_vm_verify(
+    vm: *mut Vm,
+    input: *const u8,
+    input_len: usize,
+    expected: *const u8,
+) -> bool {
+    call(false, || {
+        let vm = &mut vm.as_mut().ok_or(ErrorCode::NullPointer)?.0;
+        let expected = bytes(expected, RANDOMX_RS_HASH_SIZE)?;
+        Ok(vm.calculate_hash(bytes(input, input_len)?)?[..] == *expected)
+    })
+}
+
+/// Creates a verifier for `algorithm` (`NULL` for rx/0), keeping light-mode caches for up to
+/// `capacity` seeds. It can be used from any number of threads at once. Returns `NULL` on failure.
+///
+/// # Safety
+/// `algorithm` is `NULL` or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_verifier_new(
+    algorithm: *const c_char,
+    flags: u32,
+    capacity: usize,
+) -> *mut HashVerifier {
+    call(ptr::null_mut(), || {
+        let algorithm = self::algorithm(algorithm)?;
+        if !algorithm.is_available() {
+            return Err(ErrorCode::UnsupportedAlgorithm);
+        }
+        let verifier = Verifier::with_algorithm(algorithm, self::flags(flags)?, capacity);
+        Ok(new(HashVerifier(verifier)))
+    })
+}
+
+/// Destroys a verifier.
+///
+/// # Safety
+/// `verifier` is `NULL` or was returned by `randomx_rs_verifier_new` and not destroyed yet, and no
+/// other thread is using it.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_verifier_free(verifier: *mut HashVerifier) {
+    free(verifier)
+}
+
+/// Returns whether the hash of `input` with the cache for `seed` is the `RANDOMX_RS_HASH_SIZE`
+/// bytes at `expected`, and `false` on failure.
+///
+/// # Safety
+/// `verifier` was returned by `randomx_rs_verifier_new`, `seed` and `input` point to `seed_len`
+/// and `input_len` bytes, and `expected` to `RANDOMX_RS_HASH_SIZE` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn randomx_rs_verifier_verify(
+    verifier: *const HashVerifier,
+    seed: *const u8,
+    seed_len: usize,
+    input: *const u8,
+    input_len: usize,
+    expected: *const u8,
+) -> bool {
+    call(false, || {
+        let verifier = &object(verifier)?.0;
+        let expected = bytes(expected, RANDOMX_RS_HASH_SIZE)?;
+        let hash = verifier.hash(bytes(seed, seed_len)?, bytes(input, input_len)?)?;
+        Ok(hash[..] == *expected)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capi_flags_match() {
+        assert_eq!(RANDOMX_RS_FLAG_DEFAULT, RandomXFlag::FLAG_DEFAULT.bits());
+        assert_eq!(
+            RANDOMX_RS_FLAG_LARGE_PAGES,
+            RandomXFlag::FLAG_LARGE_PAGES.bits()
+        );
+        assert_eq!(RANDOMX_RS_FLAG_HARD_AES, RandomXFlag::FLAG_HARD_AES.bits());
+        assert_eq!(RANDOMX_RS_FLAG_FULL_MEM, RandomXFlag::FLAG_FULL_MEM.bits());
+        assert_eq!(RANDOMX_RS_FLAG_JIT, RandomXFlag::FLAG_JIT.bits());
+        assert_eq!(RANDOMX_RS_FLAG_SECURE, RandomXFlag::FLAG_SECURE.bits());
+        assert_eq!(
+            RANDOMX_RS_FLAG_ARGON2_SSSE3,
+            RandomXFlag::FLAG_ARGON2_SSSE3.bits()
+        );
+        assert_eq!(
+            RANDOMX_RS_FLAG_ARGON2_AVX2,
+            RandomXFlag::FLAG_ARGON2_AVX2.bits()
+        );
+    }
+
+    #[test]
+    fn capi_errors() {
+        let cache = unsafe { randomx_rs_cache_new(ptr::null(), 0, ptr::null(), 0) };
+        assert!(cache.is_null());
+        assert_eq!(randomx_rs_last_error(), ErrorCode::EmptyKey);
+        let key = b"Key";
+        let cache = unsafe { randomx_rs_cache_new(ptr::null(), 0, key.as_ptr(), key.len()) };
+        assert!(!cache.is_null());
+        assert_eq!(randomx_rs_last_error(), ErrorCode::Ok);
+
+        let vm = unsafe { randomx_rs_vm_new(RANDOMX_RS_FLAG_FULL_MEM, cache, ptr::null()) };
+        assert!(vm.is_null());
+        assert_eq!(randomx_rs_last_error(), ErrorCode::InvalidFlags);
+        let unknown = b"cn/r\0".as_ptr() as *const c_char;
+        let other = unsafe { randomx_rs_cache_new(unknown, 0, key.as_ptr(), key.len()) };
+        assert!(other.is_null());
+        assert_eq!(randomx_rs_last_error(), ErrorCode::UnsupportedAlgorithm);
+        let mut output = [0u8; RANDOMX_RS_HASH_SIZE];
+        let code =
+            unsafe { randomx_rs_vm_hash(ptr::null_mut(), key.as_ptr(), 3, output.as_mut_ptr()) };
+        assert_eq!(code, ErrorCode::NullPointer);
+        let message = |code| unsafe { CStr::from_ptr(randomx_rs_error_message(code)) };
+        assert_eq!(message(code as c_int).to_str().unwrap(), "null pointer");
+        assert_eq!(message(-1).to_str().unwrap(), "unknown error");
+        assert_eq!(message(1000).to_str().unwrap(), "unknown error");
+        for (i, &code) in ErrorCode::ALL.iter().enumerate() {
+            assert_eq!(ErrorCode::from_raw(i as c_int), Some(code));
+        }
+        assert_eq!(ErrorCode::ALL.len(), ErrorCode::Other as usize + 1);
+        unsafe { randomx_rs_cache_free(cache) };
+    }
+}
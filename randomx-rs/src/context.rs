@@ -0,0 +1,402 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A ready-to-hash RandomX context, see `RandomX::builder`.
+
+use crate::{
+    Algorithm, NonceHashes, RandomXCache, RandomXDataset, RandomXError, RandomXFlag, RandomXVM,
+};
+use std::sync::{Arc, RwLock};
+
+/// Whether VMs hash over the full dataset or over the cache only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// About 2 GiB for the dataset, shared by all VMs, and fast hashing (for mining)
+    Full,
+    /// About 256 MiB for the cache and slow hashing (for verifying)
+    Light,
+}
+
+/// The flags to create the cache, dataset and VMs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flags {
+    /// `RandomXFlag::get_recommended_flags`, plus FLAG_FULL_MEM in full mode
+    Auto,
+    /// Exactly these flags, plus FLAG_FULL_MEM in full mode
+    Manual(RandomXFlag),
+}
+
+impl From<RandomXFlag> for Flags {
+    fn from(flags: RandomXFlag) -> Flags {
+        Flags::Manual(flags)
+    }
+}
+
+/// Flags that apply to the cache.
+const CACHE_FLAGS: RandomXFlag = RandomXFlag::from_bits_truncate(
+    RandomXFlag::FLAG_LARGE_PAGES.bits()
+        | RandomXFlag::FLAG_JIT.bits()
+        | RandomXFlag::FLAG_ARGON2.bits(),
+);
+
+/// Builds a `RandomX` context, see `RandomX::builder`.
+#[derive(Debug, Clone)]
+pub struct RandomXBuilder {
+    algorithm: Algorithm,
+    mode: Mode,
+    flags: Flags,
+    init_threads: usize,
+    key: Option<Vec<u8>>,
+}
+
+impl RandomXBuilder {
+    /// The algorithm to hash with, rx/0 by default.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Full or light mode, full by default.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// `Flags::Auto` (the default) or a `RandomXFlag` set. FLAG_FULL_MEM follows the mode and
+    /// must not be set for light mode.
+    pub fn flags<F: Into<Flags>>(mut self, flags: F) -> Self {
+        self.flags = flags.into();
+        self
+    }
+
+    /// Number of threads to initialize the dataset on in full mode, 1 by default.
+    pub fn init_threads(mut self, threads: usize) -> Self {
+        self.init_threads = threads;
+        self
+    }
+
+    /// The key (the seed hash, for Monero) to initialize the cache with. Required.
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    /// Returns the flags VMs will be created with, or an error if they do not fit the mode or
+    /// each other.
+    pub fn vm_flags(&self) -> Result<RandomXFlag, RandomXError> {
+        let flags = match self.flags {
+            Flags::Auto => RandomXFlag::get_recommended_flags(),
+            Flags::Manual(flags) => flags,
+        };
+        if self.mode == Mode::Light && flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+            return Err(RandomXError::InvalidFlags {
+                flags,
+                reason: "FLAG_FULL_MEM needs full mode",
+            });
+        }
+        if flags.contains(RandomXFlag::FLAG_SECURE) && !flags.contains(RandomXFlag::FLAG_JIT) {
+            return Err(RandomXError::InvalidFlags {
+                flags,
+                reason: "FLAG_SECURE only applies with FLAG_JIT",
+            });
+        }
+        Ok(match self.mode {
+            Mode::Full => flags | RandomXFlag::FLAG_FULL_MEM,
+            Mode::Light => flags,
+        })
+    }
+
+    /// Checks the settings, creates the cache and, in full mode, initializes the dataset.
+    pub fn build(self) -> Result<RandomX, RandomXError> {
+        let flags = self.vm_flags()?;
+        if !self.algorithm.is_available() {
+            return Err(RandomXError::UnsupportedAlgorithm(self.algorithm));
+        }
+        if self.init_threads == 0 {
+            return Err(RandomXError::InvalidParameter("no dataset init threads"));
+        }
+        let key = self.key.ok_or(RandomXError::EmptyKey)?;
+        let inner = Inner {
+            algorithm: self.algorithm,
+            flags,
+            init_threads: self.init_threads,
+            state: RwLock::new(State::new(
+                self.algorithm,
+                flags,
+                self.init_threads,
+                key,
+                0,
+            )?),
+        };
+        Ok(RandomX {
+            inner: Arc::new(inner),
+        })
+    }
+}
+
+/// A cache, and in full mode a dataset, for one key, ready to hand out VMs.
+///
+/// Clones share the same cache and dataset. Each thread hashes with a VM of its own from `vm`;
+/// after `set_key`, every VM moves to the new key before its next hash.
+///
+/// ```no_run
+/// use randomx_rs::{Flags, Mode, RandomX};
+///
+/// let randomx = RandomX::builder()
+///     .mode(Mode::Full)
+///     .flags(Flags::Auto)
+///     .init_threads(4)
+///     .key(b"seed")
+///     .build()?;
+/// let mut vm = randomx.vm()?;
+/// let hash = vm.hash(b"input")?;
+/// # Ok::<(), randomx_rs::RandomXError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RandomX {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    algorithm: Algorithm,
+    flags: RandomXFlag,
+    init_threads: usize,
+    state: RwLock<State>,
+}
+
+/// The cache and dataset for the current key.
+#[derive(Debug)]
+struct State {
+    key: Vec<u8>,
+    /// Increased on every key change, for VMs to notice it.
+    generation: u64,
+    cache: RandomXCache,
+    dataset: Option<RandomXDataset>,
+}
+
+impl State {
+    fn new(
+        algorithm: Algorithm,
+        flags: RandomXFlag,
+        init_threads: usize,
+        key: Vec<u8>,
+        generation: u64,
+    ) -> Result<State, RandomXError> {
+        let cache = RandomXCache::with_algorithm(algorithm, flags & CACHE_FLAGS, &key)?;
+        let dataset = if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+            let dataset_flags = flags & RandomXFlag::FLAG_LARGE_PAGES;
+            Some(RandomXDataset::new_parallel(
+                dataset_flags,
+                &cache,
+                0,
+                init_threads,
+                |_, _| {},
+            )?)
+        } else {
+            None
+        };
+        Ok(State {
+            key,
+            generation,
+            cache,
+            dataset,
+        })
+    }
+}
+
+impl RandomX {
+    /// Returns a builder for a context, see `RandomXBuilder`.
+    pub fn builder() -> RandomXBuilder {
+        RandomXBuilder {
+            algorithm: Algorithm::Rx0,
+            mode: Mode::Full,
+            flags: Flags::Auto,
+            init_threads: 1,
+            key: None,
+        }
+    }
+
+    /// Returns the algorithm the context hashes with.
+    pub fn algorithm(&self) -> Algorithm {
+        self.inner.algorithm
+    }
+
+    /// Returns the flags VMs are created with.
+    pub fn flags(&self) -> RandomXFlag {
+        self.inner.flags
+    }
+
+    /// Returns the current key.
+    pub fn key(&self) -> Vec<u8> {
+        self.inner.state.read().unwrap().key.clone()
+    }
+
+    /// Moves the context to `key`. The new cache and dataset are built before the old ones are
+    /// replaced, so hashing with the old key goes on meanwhile; they are released once the last
+    /// VM has moved to the new key.
+    pub fn set_key(&self, key: &[u8]) -> Result<(), RandomXError> {
+        let generation = {
+            let state = self.inner.state.read().unwrap();
+            if state.key == key {
+                return Ok(());
+            }
+            state.generation
+        };
+        let inner = &self.inner;
+        let state = State::new(
+            inner.algorithm,
+            inner.flags,
+            inner.init_threads,
+            key.to_vec(),
+            generation + 1,
+        )?;
+        let mut current = inner.state.write().unwrap();
+        // of two concurrent key changes, the last one to finish wins
+        *current = State {
+            generation: current.generation + 1,
+            ..state
+        };
+        Ok(())
+    }
+
+    /// Creates a VM for the calling thread.
+    pub fn vm(&self) -> Result<ContextVm, RandomXError> {
+        let state = self.inner.state.read().unwrap();
+        let vm = RandomXVM::new(self.inner.flags, Some(&state.cache), state.dataset.as_ref())?;
+        Ok(ContextVm {
+            context: self.clone(),
+            vm,
+            generation: state.generation,
+        })
+    }
+}
+
+/// A VM handed out by `RandomX::vm`, which follows the key of the context.
+#[derive(Debug)]
+pub struct ContextVm {
+    context: RandomX,
+    vm: RandomXVM,
+    generation: u64,
+}
+
+impl ContextVm {
+    /// Calculates the RandomX hash of `input` with the current key of the context.
+    pub fn hash(&mut self, input: &[u8]) -> Result<[u8; 32], RandomXError> {
+        self.vm()?.calculate_hash(input)
+    }
+
+    /// Hashes `blob` with each of `nonces`, see `RandomXVM::hash_nonces`. The key is the current
+    /// one when this is called; a key change only takes effect on the next call.
+    pub fn hash_nonces<'a, I>(
+        &'a mut self,
+        blob: &'a mut [u8],
+        nonce_offset: usize,
+        nonces: I,
+    ) -> Result<NonceHashes<'a, I::IntoIter>, RandomXError>
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        self.vm()?.hash_nonces(blob, nonce_offset, nonces)
+    }
+
+    /// Returns the VM, moved to the current key of the context.
+    pub fn vm(&mut self) -> Result<&mut RandomXVM, RandomXError> {
+        let state = self.context.inner.state.read().unwrap();
+        if state.generation != self.generation {
+            match &state.dataset {
+                Some(dataset) => self.vm.reinit_dataset(dataset)?,
+                None => self.vm.reinit_cache(&state.cache)?,
+            }
+            self.generation = state.generation;
+        }
+        Ok(&mut self.vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Flags, Mode, RandomX, RandomXError, RandomXFlag, TEST_VECTORS};
+    use std::thread;
+
+    #[test]
+    fn context_light() {
+        let a = &TEST_VECTORS[0];
+        let b = &TEST_VECTORS[3];
+        let randomx = RandomX::builder()
+            .mode(Mode::Light)
+            .key(a.key)
+            .build()
+            .unwrap();
+        assert!(!randomx.flags().contains(RandomXFlag::FLAG_FULL_MEM));
+        let mut vm = randomx.vm().unwrap();
+        assert_eq!(vm.hash(a.input).unwrap(), a.hash);
+
+        randomx.set_key(b.key).unwrap();
+        assert_eq!(randomx.key(), b.key);
+        assert_eq!(vm.hash(b.input).unwrap(), b.hash);
+    }
+
+    #[test]
+    fn context_full() {
+        let vector = &TEST_VECTORS[0];
+        let randomx = RandomX::builder()
+            .mode(Mode::Full)
+            .flags(Flags::Auto)
+            .init_threads(4)
+            .key(vector.key)
+            .build()
+            .unwrap();
+        assert!(randomx.flags().contains(RandomXFlag::FLAG_FULL_MEM));
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                let mut vm = randomx.vm().unwrap();
+                scope.spawn(move || assert_eq!(vm.hash(vector.input).unwrap(), vector.hash));
+            }
+        });
+    }
+
+    #[test]
+    fn context_checks_settings() {
+        let light = RandomX::builder().mode(Mode::Light).key(b"key");
+        let err = light
+            .clone()
+            .flags(RandomXFlag::FLAG_FULL_MEM)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, RandomXError::InvalidFlags { .. }));
+        let err = light
+            .clone()
+            .flags(RandomXFlag::FLAG_SECURE)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, RandomXError::InvalidFlags { .. }));
+        assert_eq!(
+            light.clone().init_threads(0).build().unwrap_err(),
+            RandomXError::InvalidParameter("no dataset init threads")
+        );
+        assert_eq!(
+            RandomX::builder().mode(Mode::Light).build().unwrap_err(),
+            RandomXError::EmptyKey
+        );
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+//! Saving an initialized dataset to a file and loading it back.
+//!
+//! A dataset file starts with a header that ties it to the key the dataset was initialized from
+//! and to the RandomX build that initialized it, followed by the raw dataset memory:
+//!
+//! | field            | size             |
+//! |------------------|------------------|
+//! | magic            | 8                |
+//! | format version   | 4                |
+//! | algorithm name   | 2 + length       |
+//! | crate version    | 2 + length       |
+//! | library id       | 32               |
+//! | item size        | 4                |
+//! | item count       | 8                |
+//! | key              | 4 + length       |
+//! | checksum of data | 8                |
+//! | data             | item size * item count |
+//!
+//! All integers are little-endian. The flags are not part of the header: they select how the
+//! dataset is computed and allocated, but not its contents.
+//!
+//! The library id identifies the linked RandomX build, which the crate version does not when
+//! librandomx comes from the system: it is the light-mode hash of a fixed input under a fixed
+//! key, so any build or configuration that computes a different dataset has a different id.
+
+use crate::bindings::RANDOMX_DATASET_ITEM_SIZE;
+use crate::{
+    Algorithm, Allocation, RandomXCache, RandomXDataset, RandomXDatasetInner, RandomXError,
+    RandomXFlag, RandomXVM,
+};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+const MAGIC: &[u8; 8] = b"RXDATSET";
+const FORMAT_VERSION: u32 = 3;
+
+static SAVES: AtomicUsize = AtomicUsize::new(0);
+
+/// Library ids by algorithm, computed once per process.
+static LIBRARY_IDS: Mutex<Vec<(Algorithm, [u8; 32])>> = Mutex::new(Vec::new());
+
+impl RandomXDataset {
+    /// Writes the dataset to the file at `path`, tagged with its algorithm and the `key` of the
+    /// cache it was initialized from. The file is written next to `path` first and then renamed,
+    /// so a reader never sees a partial file.
+    pub fn save<P: AsRef<Path>>(&self, path: P, key: &[u8]) -> io::Result<()> {
+        let data = self.as_bytes().map_err(other)?;
+
+        let path = path.as_ref();
+        // unique, for datasets of the same key saved at the same time (e.g. one per NUMA node)
+        let unique = SAVES.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}-{}.tmp", process::id(), unique));
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        let algorithm = self.algorithm();
+        write_header(
+            &mut file,
+            algorithm,
+            &library_id(algorithm).map_err(other)?,
+            key,
+            item_count(algorithm),
+            checksum(data),
+        )?;
+        file.write_all(data)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Allocates a dataset and loads it from the file at `path`, which must have been saved with
+    /// the same `key` by the same version of this library.
+    ///
+    /// `flags` is one of the following:
+    /// * FLAG_DEFAULT
+    /// * FLAG_LARGE_PAGES
+    ///
+    /// Returns an error of kind `InvalidData` if the file is for another key, algorithm, library
+    /// version or RandomX build, or if it is corrupt. If the dataset cannot be allocated, the
+    /// error wraps a `RandomXError`.
+    pub fn load<P: AsRef<Path>>(
+        flags: RandomXFlag,
+        path: P,
+        key: &[u8],
+    ) -> io::Result<RandomXDataset> {
+        RandomXDataset::load_with_algorithm(Algorithm::Rx0, flags, path, key)
+    }
+
+    /// Loads a dataset for `algorithm` as in `load`. If the library was not built with
+    /// `algorithm`, the error wraps a `RandomXError`.
+    pub fn load_with_algorithm<P: AsRef<Path>>(
+        algorithm: Algorithm,
+        flags: RandomXFlag,
+        path: P,
+        key: &[u8],
+    ) -> io::Result<RandomXDataset> {
+        let api = algorithm
+            .api()
+            .ok_or_else(|| other(RandomXError::UnsupportedAlgorithm(algorithm)))?;
+        let mut file = BufReader::new(File::open(path)?);
+        let count = item_count(algorithm);
+        let id = library_id(algorithm).map_err(other)?;
+        let expected = read_header(&mut file, algorithm, &id, key, count)?;
+
+        let test = unsafe { (api.alloc_dataset)(flags.bits) };
+        if test.is_null() {
+            return Err(other(RandomXError::allocation(Allocation::Dataset, flags)));
+        }
+        let result = RandomXDataset {
+            inner: Arc::new(RandomXDatasetInner {
+                dataset: test,
+                algorithm,
+            }),
+        };
+
+        // read straight into the dataset memory, which nothing else can see yet
+        let (memory, len) = result.memory().map_err(other)?;
+        let data = unsafe { slice::from_raw_parts_mut(memory, len) };
+        file.read_exact(data).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("dataset file is truncated"),
+            _ => e,
+        })?;
+        if file.read(&mut [0u8])? != 0 {
+            return Err(invalid("dataset file is too long"));
+        }
+        if checksum(data) != expected {
+            return Err(invalid("dataset file checksum mismatch"));
+        }
+        Ok(result)
+    }
+}
+
+/// Light-mode hash of a fixed input under a fixed key, with the interpreter, to identify the
+/// RandomX build linked for `algorithm`.
+fn library_id(algorithm: Algorithm) -> Result<[u8; 32], RandomXError> {
+    let mut ids = LIBRARY_IDS.lock().unwrap();
+    if let Some((_, id)) = ids.iter().find(|(a, _)| *a == algorithm) {
+        return Ok(*id);
+    }
+    let flags = RandomXFlag::FLAG_DEFAULT;
+    let cache = RandomXCache::with_algorithm(algorithm, flags, b"randomx-rs dataset file")?;
+    let id = RandomXVM::new(flags, Some(&cache), None)?.calculate_hash(b"library id")?;
+    ids.push((algorithm, id));
+    Ok(id)
+}
+
+fn item_count(algorithm: Algorithm) -> u64 {
+    unsafe { (algorithm.ffi().dataset_item_count)() }
+}
+
+fn other(e: RandomXError) -> io::Error {
+    io::Error::other(e)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// FNV-1a over little-endian 64-bit words.
+fn checksum(data: &[u8]) -> u64 {
+    data.chunks(8).fold(0xcbf2_9ce4_8422_2325, |hash, word| {
+        let mut buf = [0u8; 8];
+        buf[..word.len()].copy_from_slice(word);
+        (hash ^ u64::from_le_bytes(buf)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn write_header<W: Write>(
+    w: &mut W,
+    algorithm: Algorithm,
+    library_id: &[u8; 32],
+    key: &[u8],
+    count: u64,
+    checksum: u64,
+) -> io::Result<()> {
+    let version = env!("CARGO_PKG_VERSION").as_bytes();
+    let name = algorithm.name().as_bytes();
+    w.write_all(MAGIC)?;
+    w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    w.write_all(&(name.len() as u16).to_le_bytes())?;
+    w.write_all(name)?;
+    w.write_all(&(version.len() as u16).to_le_bytes())?;
+    w.write_all(version)?;
+    w.write_all(library_id)?;
+    w.write_all(&RANDOMX_DATASET_ITEM_SIZE.to_le_bytes())?;
+    w.write_all(&count.to_le_bytes())?;
+    w.write_all(&(key.len() as u32).to_le_bytes())?;
+    w.write_all(key)?;
+    w.write_all(&checksum.to_le_bytes())
+}
+
+/// Checks the header against the current library, `algorithm` and `key`, and returns the data
+/// checksum.
+fn read_header<R: Read>(
+    r: &mut R,
+    algorithm: Algorithm,
+    library_id: &[u8; 32],
+    key: &[u8],
+    count: u64,
+) -> io::Result<u64> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a dataset file"));
+    }
+    if read_u32(r)? != FORMAT_VERSION {
+        return Err(invalid("unsupported dataset file format"));
+    }
+    let mut name = vec![0u8; read_u16(r)? as usize];
+    r.read_exact(&mut name)?;
+    if name != algorithm.name().as_bytes() {
+        return Err(invalid("dataset file is for another algorithm"));
+    }
+    let mut version = vec![0u8; read_u16(r)? as usize];
+    r.read_exact(&mut version)?;
+    if version != env!("CARGO_PKG_VERSION").as_bytes() {
+        return Err(invalid("dataset file is from another library version"));
+    }
+    let mut id = [0u8; 32];
+    r.read_exact(&mut id)?;
+    if &id != library_id {
+        return Err(invalid("dataset file is from another RandomX build"));
+    }
+    if read_u32(r)? != RANDOMX_DATASET_ITEM_SIZE || read_u64(r)? != count {
+        return Err(invalid("dataset file has a different layout"));
+    }
+    let key_len = read_u32(r)? as usize;
+    if key_len != key.len() {
+        return Err(invalid("dataset file is for another key"));
+    }
+    let mut file_key = vec![0u8; key_len];
+    r.read_exact(&mut file_key)?;
+    if file_key != key {
+        return Err(invalid("dataset file is for another key"));
+    }
+    read_u64(r)
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{item_count, library_id, read_header, write_header};
+    use crate::{Algorithm, RandomXCache, RandomXDataset, RandomXFlag, RandomXVM};
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Seek, SeekFrom, Write};
+
+    #[test]
+    fn dataset_save_load() {
+        let flags = RandomXFlag::default();
+        let key = "Key";
+        let input = "Input";
+        let path =
+            std::env::temp_dir().join(format!("randomx-rs-test-{}.rxds", std::process::id()));
+        let cache = RandomXCache::new(flags, key.as_bytes()).unwrap();
+        let dataset = RandomXDataset::new(flags, &cache, 0).unwrap();
+        dataset.save(&path, key.as_bytes()).unwrap();
+
+        let loaded = RandomXDataset::load(flags, &path, key.as_bytes()).unwrap();
+        let flags2 = flags | RandomXFlag::FLAG_FULL_MEM;
+        let mut vm1 = RandomXVM::new(flags2, None, Some(&dataset)).unwrap();
+        let mut vm2 = RandomXVM::new(flags2, None, Some(&loaded)).unwrap();
+        assert_eq!(
+            vm1.calculate_hash(input.as_bytes()).unwrap(),
+            vm2.calculate_hash(input.as_bytes()).unwrap()
+        );
+        drop(loaded);
+
+        let stale = RandomXDataset::load(flags, &path, "Other key".as_bytes());
+        assert_eq!(stale.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.seek(SeekFrom::End(0)).unwrap();
+        file.seek(SeekFrom::Start(len / 2)).unwrap();
+        file.write_all(&[0xff; 16]).unwrap();
+        drop(file);
+        let corrupt = RandomXDataset::load(flags, &path, key.as_bytes());
+        assert_eq!(corrupt.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 1).unwrap();
+        drop(file);
+        let truncated = RandomXDataset::load(flags, &path, key.as_bytes());
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn other_randomx_build() {
+        let algorithm = Algorithm::Rx0;
+        let count = item_count(algorithm);
+        let id = library_id(algorithm).unwrap();
+        assert_eq!(library_id(algorithm).unwrap(), id);
+        let mut header = Vec::new();
+        write_header(&mut header, algorithm, &id, b"Key", count, 0).unwrap();
+        assert!(read_header(&mut &header[..], algorithm, &id, b"Key", count).is_ok());
+        let other = [0xff; 32];
+        let err = read_header(&mut &header[..], algorithm, &other, b"Key", count).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "dataset file is from another RandomX build"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{Algorithm, RandomXFlag};
+use std::error::Error;
+use std::fmt;
+
+/// The kind of RandomX object whose allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Allocation {
+    Cache,
+    Dataset,
+    Vm,
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Allocation::Cache => f.write_str("cache"),
+            Allocation::Dataset => f.write_str("dataset"),
+            Allocation::Vm => f.write_str("vm"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Custom error enum
+pub enum RandomXError {
+    /// Allocating a cache, dataset or VM failed
+    AllocationFailed {
+        what: Allocation,
+        flags: RandomXFlag,
+    },
+    /// Allocating with FLAG_LARGE_PAGES failed, retrying without it may succeed
+    LargePagesFailed {
+        what: Allocation,
+        flags: RandomXFlag,
+    },
+    /// The flags cannot be used for this operation
+    InvalidFlags {
+        flags: RandomXFlag,
+        reason: &'static str,
+    },
+    /// The key is empty
+    EmptyKey,
+    /// The input is empty
+    EmptyInput,
+    /// A parameter is out of range
+    InvalidParameter(&'static str),
+    /// The hash of a test vector did not match, see `self_test`
+    SelfTestFailed { flags: RandomXFlag, index: usize },
+    /// Dataset initialization did not cover every item
+    DatasetIncomplete { initialized: u64, total: u64 },
+    /// The library was not built with the algorithm, see `Algorithm::is_available`
+    UnsupportedAlgorithm(Algorithm),
+    /// Problem running RandomX
+    Other(&'static str),
+}
+
+impl RandomXError {
+    /// Returns whether this is a failure to allocate memory, with or without large pages.
+    pub fn is_allocation(&self) -> bool {
+        matches!(
+            self,
+            RandomXError::AllocationFailed { .. } | RandomXError::LargePagesFailed { .. }
+        )
+    }
+
+    /// Returns the error for a failed allocation of `what` with `flags`.
+    pub(crate) fn allocation(what: Allocation, flags: RandomXFlag) -> RandomXError {
+        if flags.contains(RandomXFlag::FLAG_LARGE_PAGES) {
+            RandomXError::LargePagesFailed { what, flags }
+        } else {
+            RandomXError::AllocationFailed { what, flags }
+        }
+    }
+}
+
+impl fmt::Display for RandomXError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RandomXError::AllocationFailed { what, flags } => {
+                write!(
+                    f,
+                    "failed to allocate RandomX {} (flags: {:?})",
+                    what, flags
+                )
+            }
+            RandomXError::LargePagesFailed { what, flags } => write!(
+                f,
+                "failed to allocate RandomX {} in large pages (flags: {:?})",
+                what, flags
+            ),
+            RandomXError::InvalidFlags { flags, reason } => {
+                write!(f, "invalid RandomX flags {:?}: {}", flags, reason)
+            }
+            RandomXError::EmptyKey => f.write_str("RandomX key is empty"),
+            RandomXError::EmptyInput => f.write_str("RandomX input is empty"),
+            RandomXError::InvalidParameter(msg) => write!(f, "invalid parameter: {}", msg),
+            RandomXError::SelfTestFailed { flags, index } => write!(
+                f,
+                "wrong hash for test vector {} with flags {:?}",
+                index, flags
+            ),
+            RandomXError::DatasetIncomplete { initialized, total } => write!(
+                f,
+                "dataset initialization incomplete: {} of {} items",
+                initialized, total
+            ),
+            RandomXError::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "{} is not built into the RandomX library", algorithm)
+            }
+            RandomXError::Other(msg) => write!(f, "RandomX error: {}", msg),
+        }
+    }
+}
+
+impl Error for RandomXError {}
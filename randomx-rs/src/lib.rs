@@ -24,26 +24,35 @@
 //! The `randomx-rs` crate provides bindings to the `RandomX` proof-of-work (PoW) system as well
 //! as the functionality to utilize these bindings.
 //!
+mod algorithm;
 mod bindings;
+pub mod capi;
+mod context;
+mod dataset_file;
+mod error;
+mod test_vectors;
+mod verifier;
 #[macro_use]
 extern crate bitflags;
 extern crate libc;
 
 use bindings::{
-    randomx_alloc_cache, randomx_alloc_dataset, randomx_cache, randomx_calculate_hash,
-    randomx_create_vm, randomx_dataset, randomx_dataset_item_count, randomx_destroy_vm,
-    randomx_get_dataset_memory, randomx_init_cache, randomx_init_dataset, randomx_release_cache,
-    randomx_release_dataset, randomx_vm, randomx_vm_set_cache, randomx_vm_set_dataset,
-    RANDOMX_DATASET_ITEM_SIZE, RANDOMX_HASH_SIZE,
+    randomx_cache, randomx_dataset, randomx_get_flags, randomx_vm, Api, RANDOMX_DATASET_ITEM_SIZE,
+    RANDOMX_HASH_SIZE,
 };
 
-use crate::bindings::{
-    randomx_calculate_hash_first, randomx_calculate_hash_last, randomx_calculate_hash_next,
-    randomx_get_flags,
-};
-use derive_error::Error;
 use libc::{c_ulong, c_void};
 use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub use algorithm::Algorithm;
+pub use context::{ContextVm, Flags, Mode, RandomX, RandomXBuilder};
+pub use error::{Allocation, RandomXError};
+pub use test_vectors::{self_test, self_test_algorithm, TestVector, TEST_VECTORS};
+pub use verifier::Verifier;
 
 bitflags! {
 /// Indicates to the RandomX library which configuration options to use.
@@ -94,34 +103,35 @@ impl Default for RandomXFlag {
     }
 }
 
-#[derive(Debug, Clone, Error)]
-/// Custom error enum
-pub enum RandomXError {
-    /// Problem creating the RandomX object
-    CreationError,
-    /// Problem with configuration flags
-    FlagConfigError,
-    /// Problem with parameters supplied
-    ParameterError,
-    /// Problem running RandomX
-    Other,
-}
-
 #[derive(Debug)]
-/// Cache structure
-pub struct RandomXCache {
+struct RandomXCacheInner {
     cache: *mut randomx_cache,
+    algorithm: Algorithm,
 }
 
-impl Drop for RandomXCache {
+impl Drop for RandomXCacheInner {
     /// De-allocates memory for the `cache` object
     fn drop(&mut self) {
         unsafe {
-            randomx_release_cache(self.cache);
+            (self.algorithm.ffi().release_cache)(self.cache);
         }
     }
 }
 
+// After `randomx_init_cache` returns, the cache memory is only ever read (by
+// `randomx_init_dataset` and by light-mode VMs), so it can be shared between threads.
+unsafe impl Send for RandomXCacheInner {}
+unsafe impl Sync for RandomXCacheInner {}
+
+#[derive(Debug, Clone)]
+/// Cache structure
+///
+/// Clones share the same cache memory, which is released when the last clone is dropped. VMs
+/// using the cache hold a clone of their own, so it cannot be released while in use.
+pub struct RandomXCache {
+    inner: Arc<RandomXCacheInner>,
+}
+
 impl RandomXCache {
     /// Creates a new cache object, allocates memory to the `cache` object and initializes it with
     /// he key value, error on failure.
@@ -136,42 +146,83 @@ impl RandomXCache {
     ///
     /// `key` is a sequence of u8 used to initialize SuperScalarHash.
     pub fn new(flags: RandomXFlag, key: &[u8]) -> Result<RandomXCache, RandomXError> {
+        RandomXCache::with_algorithm(Algorithm::Rx0, flags, key)
+    }
+
+    /// Creates a new cache object for `algorithm`, error on failure or if the library was not
+    /// built with `algorithm`. Datasets and VMs created from the cache use the same algorithm.
+    ///
+    /// `flags` and `key` are as in `new`.
+    pub fn with_algorithm(
+        algorithm: Algorithm,
+        flags: RandomXFlag,
+        key: &[u8],
+    ) -> Result<RandomXCache, RandomXError> {
+        let api = algorithm
+            .api()
+            .ok_or(RandomXError::UnsupportedAlgorithm(algorithm))?;
         if key.is_empty() {
-            return Err(RandomXError::ParameterError);
+            return Err(RandomXError::EmptyKey);
         };
-        let test = unsafe { randomx_alloc_cache(flags.bits) };
+        let test = unsafe { (api.alloc_cache)(flags.bits) };
         if test.is_null() {
-            Err(RandomXError::CreationError)
+            Err(RandomXError::allocation(Allocation::Cache, flags))
         } else {
-            let result = RandomXCache { cache: test };
+            let result = RandomXCache {
+                inner: Arc::new(RandomXCacheInner {
+                    cache: test,
+                    algorithm,
+                }),
+            };
             let key_ptr = key.as_ptr() as *mut c_void;
             let key_size = key.len() as usize;
             unsafe {
                 //no way to check if this fails, c code does not return anything
-                randomx_init_cache(result.cache, key_ptr, key_size);
+                (api.init_cache)(result.as_ptr(), key_ptr, key_size);
             }
             Ok(result)
         }
     }
+
+    /// Returns the algorithm the cache was created for.
+    pub fn algorithm(&self) -> Algorithm {
+        self.inner.algorithm
+    }
+
+    fn as_ptr(&self) -> *mut randomx_cache {
+        self.inner.cache
+    }
 }
 
 #[derive(Debug)]
-/// Dataset structure
-pub struct RandomXDataset {
+struct RandomXDatasetInner {
     dataset: *mut randomx_dataset,
-    dataset_start: c_ulong,
-    dataset_count: c_ulong,
+    algorithm: Algorithm,
 }
 
-impl Drop for RandomXDataset {
+impl Drop for RandomXDatasetInner {
     /// De-allocates memory for the `dataset` object.
     fn drop(&mut self) {
         unsafe {
-            randomx_release_dataset(self.dataset);
+            (self.algorithm.ffi().release_dataset)(self.dataset);
         }
     }
 }
 
+// The dataset is read-only once initialized; every VM reads it through its own scratchpad, so
+// one dataset can back VMs on any number of threads.
+unsafe impl Send for RandomXDatasetInner {}
+unsafe impl Sync for RandomXDatasetInner {}
+
+#[derive(Debug, Clone)]
+/// Dataset structure
+///
+/// Clones share the same dataset memory, which is released when the last clone is dropped. VMs
+/// using the dataset hold a clone of their own, so it cannot be released while in use.
+pub struct RandomXDataset {
+    inner: Arc<RandomXDatasetInner>,
+}
+
 impl RandomXDataset {
     /// Creates a new dataset object, allocates memory to the `dataset` object and initializes it,
     /// error on failure.
@@ -183,88 +234,253 @@ impl RandomXDataset {
     /// `cache` is a cache object.
     ///
     /// `start` is the item number where initialization should start, recommended to pass in 0.
+    /// Items from `start` to the end of the dataset are initialized.
     pub fn new(
         flags: RandomXFlag,
         cache: &RandomXCache,
         start: c_ulong,
     ) -> Result<RandomXDataset, RandomXError> {
-        // let count = c_ulong::from(RANDOMX_DATASET_ITEM_SIZE - 1) - start;
-        let count: c_ulong = unsafe { randomx_dataset_item_count() } as u64;
-        let test = unsafe { randomx_alloc_dataset(flags.bits) };
+        RandomXDataset::new_with(flags, cache, start, 1, &|_, _| {}, |slices| {
+            for slice in slices {
+                slice.init();
+            }
+        })
+    }
+
+    /// Creates a new dataset object and initializes it on `threads` threads, error on failure.
+    ///
+    /// The items from `start` to the end of the dataset are split into `threads` contiguous
+    /// slices, each initialized on its own thread.
+    ///
+    /// `progress` is called from the initializing threads with the number of items initialized
+    /// so far and the total number of items to initialize.
+    pub fn new_parallel<F>(
+        flags: RandomXFlag,
+        cache: &RandomXCache,
+        start: c_ulong,
+        threads: usize,
+        progress: F,
+    ) -> Result<RandomXDataset, RandomXError>
+    where
+        F: Fn(u64, u64) + Sync,
+    {
+        RandomXDataset::new_with(flags, cache, start, threads, &progress, |slices| {
+            thread::scope(|scope| {
+                let handles: Vec<_> = slices
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, slice)| {
+                        thread::Builder::new()
+                            .name(format!("rx-dataset-{}", i))
+                            .spawn_scoped(scope, move || slice.init())
+                            .ok()
+                    })
+                    .collect();
+                // a slice that failed to spawn or panicked is caught by the item count check
+                for handle in handles {
+                    let _ = handle.join();
+                }
+            })
+        })
+    }
+
+    /// Creates a new dataset object and initializes it with a caller-supplied executor, error on
+    /// failure.
+    ///
+    /// The items from `start` to the end of the dataset are split into `slices` contiguous
+    /// `DatasetSlice`s, which are handed to `run`. `run` must call `DatasetSlice::init` on every
+    /// slice before returning (e.g. by spawning each on a scoped thread pool); if any slice was not
+    /// initialized, an error is returned.
+    ///
+    /// `progress` is called as in `new_parallel`.
+    pub fn new_with<R>(
+        flags: RandomXFlag,
+        cache: &RandomXCache,
+        start: c_ulong,
+        slices: usize,
+        progress: &(dyn Fn(u64, u64) + Sync),
+        run: R,
+    ) -> Result<RandomXDataset, RandomXError>
+    where
+        R: FnOnce(Vec<DatasetSlice<'_>>),
+    {
+        let algorithm = cache.algorithm();
+        let api = algorithm.ffi();
+        let item_count = match unsafe { (api.dataset_item_count)() } {
+            0 => return Err(RandomXError::Other("dataset item count is zero")),
+            x => x,
+        };
+        // Mirror the assert checks inside randomx_init_dataset call
+        if start >= item_count {
+            return Err(RandomXError::InvalidParameter(
+                "start is past the end of the dataset",
+            ));
+        }
+        if slices == 0 {
+            return Err(RandomXError::InvalidParameter("no dataset slices"));
+        }
+        let count = item_count - start;
+
+        let test = unsafe { (api.alloc_dataset)(flags.bits) };
         if test.is_null() {
-            Err(RandomXError::CreationError)
-        } else {
-            let result = RandomXDataset {
+            return Err(RandomXError::allocation(Allocation::Dataset, flags));
+        }
+        let result = RandomXDataset {
+            inner: Arc::new(RandomXDatasetInner {
                 dataset: test,
-                dataset_start: start,
-                dataset_count: count,
-            };
-            let item_count = match result.count() {
-                Ok(v) => v,
-                Err(_) => return Err(RandomXError::CreationError),
-            };
-            // Mirror the assert checks inside randomx_init_dataset call
-            if !((start < (item_count as c_ulong) && count <= (item_count as c_ulong))
-                || (start + (item_count as c_ulong) <= count))
-            {
-                return Err(RandomXError::CreationError);
-            }
+                algorithm,
+            }),
+        };
 
-            unsafe {
-                //no way to check if this fails, c code does not return anything
-                randomx_init_dataset(
-                    result.dataset,
-                    cache.cache,
-                    start as c_ulong,
-                    count as c_ulong,
-                );
-            }
-            Ok(result)
+        let done = AtomicU64::new(0);
+        let per_slice = count.div_ceil(slices as c_ulong);
+        let parts = (0..slices as c_ulong)
+            .map(|i| start + i * per_slice)
+            .take_while(|&slice_start| slice_start < item_count)
+            .map(|slice_start| DatasetSlice {
+                dataset: &result,
+                cache,
+                start: slice_start,
+                count: per_slice.min(item_count - slice_start),
+                done: &done,
+                total: count,
+                progress,
+            })
+            .collect();
+        run(parts);
+
+        let initialized = done.load(Ordering::Acquire);
+        if initialized != count {
+            return Err(RandomXError::DatasetIncomplete {
+                initialized,
+                total: count,
+            });
         }
+        Ok(result)
+    }
+
+    /// Returns the algorithm the dataset was created for.
+    pub fn algorithm(&self) -> Algorithm {
+        self.inner.algorithm
+    }
+
+    fn as_ptr(&self) -> *mut randomx_dataset {
+        self.inner.dataset
+    }
+
+    fn api(&self) -> &'static Api {
+        self.inner.algorithm.ffi()
     }
 
     /// Returns the number of items in the `dataset` or an error on failure.
     pub fn count(&self) -> Result<u64, RandomXError> {
-        match unsafe { randomx_dataset_item_count() } {
-            0 => Err(RandomXError::Other),
-            x => Ok(x as u64),
+        match unsafe { (self.api().dataset_item_count)() } {
+            0 => Err(RandomXError::Other("dataset item count is zero")),
+            x => Ok(x),
         }
     }
 
-    /// Returns the values of the internal memory buffer of the `dataset` or an error on failure.
+    /// Returns a copy of the internal memory buffer of the `dataset` or an error on failure.
     pub fn get_data(&self) -> Result<Vec<u8>, RandomXError> {
-        let memory = unsafe { randomx_get_dataset_memory(self.dataset) };
+        Ok(self.as_bytes()?.to_vec())
+    }
+
+    /// Returns the internal memory buffer of the `dataset` without copying it, or an error on
+    /// failure. It holds `count()` items of `RANDOMX_DATASET_ITEM_SIZE` bytes each.
+    pub fn as_bytes(&self) -> Result<&[u8], RandomXError> {
+        let (memory, len) = self.memory()?;
+        // the dataset is only written while it is being created or loaded
+        Ok(unsafe { slice::from_raw_parts(memory, len) })
+    }
+
+    /// Returns a pointer to the internal memory buffer of the `dataset` and its size in bytes.
+    fn memory(&self) -> Result<(*mut u8, usize), RandomXError> {
+        let memory = unsafe { (self.api().get_dataset_memory)(self.as_ptr()) };
         if memory.is_null() {
-            return Err(RandomXError::Other);
+            return Err(RandomXError::Other("dataset memory is null"));
         }
-        let mut result: Vec<u8> = vec![0u8; self.dataset_count as usize];
-        unsafe {
-            libc::memcpy(
-                result.as_mut_ptr() as *mut c_void,
-                memory,
-                self.dataset_count as usize,
-            );
+        let len = self.count()? as usize * RANDOMX_DATASET_ITEM_SIZE as usize;
+        Ok((memory as *mut u8, len))
+    }
+}
+
+/// A contiguous range of dataset items to be initialized, see `RandomXDataset::new_with`.
+pub struct DatasetSlice<'a> {
+    dataset: &'a RandomXDataset,
+    cache: &'a RandomXCache,
+    start: c_ulong,
+    count: c_ulong,
+    done: &'a AtomicU64,
+    total: u64,
+    progress: &'a (dyn Fn(u64, u64) + Sync),
+}
+
+// Slices of one dataset never overlap, so each can be initialized on its own thread.
+unsafe impl Send for DatasetSlice<'_> {}
+
+impl DatasetSlice<'_> {
+    /// Number of items initialized between progress reports.
+    const CHUNK_ITEMS: c_ulong = 1 << 18;
+
+    /// Returns the number of the first item in this slice.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the number of items in this slice.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Initializes the items in this slice from the cache.
+    pub fn init(self) {
+        let end = self.start + self.count;
+        let mut item = self.start;
+        while item < end {
+            let chunk = DatasetSlice::CHUNK_ITEMS.min(end - item);
+            unsafe {
+                //no way to check if this fails, c code does not return anything
+                (self.dataset.api().init_dataset)(
+                    self.dataset.as_ptr(),
+                    self.cache.as_ptr(),
+                    item,
+                    chunk,
+                );
+            }
+            item += chunk;
+            let done = self.done.fetch_add(chunk, Ordering::AcqRel) + chunk;
+            (self.progress)(done, self.total);
         }
-        Ok(result)
     }
 }
 
 #[derive(Debug)]
 /// VM structure
+///
+/// The VM holds on to the cache and dataset it was created or re-initialized with, so they stay
+/// alive for as long as it uses them. A FLAG_FULL_MEM VM only reads its dataset, and does not
+/// keep a cache alive.
 pub struct RandomXVM {
     flags: RandomXFlag,
+    algorithm: Algorithm,
     vm: *mut randomx_vm,
+    linked_cache: Option<RandomXCache>,
+    linked_dataset: Option<RandomXDataset>,
 }
 
 impl Drop for RandomXVM {
     /// De-allocates memory for the `VM` object.
     fn drop(&mut self) {
         unsafe {
-            randomx_destroy_vm(self.vm);
+            (self.api().destroy_vm)(self.vm);
         }
     }
 }
 
+// A VM can be moved to another thread, but it is not `Sync`: its scratchpad and registers are
+// mutated by every hash, which is why the hashing methods take `&mut self`.
+unsafe impl Send for RandomXVM {}
+
 impl RandomXVM {
     /// Creates a new `VM` and initializes it, error on failure.
     ///
@@ -282,6 +498,8 @@ impl RandomXVM {
     /// `cache` is a cache object, optional if FLAG_FULL_MEM is set.
     ///
     /// `dataset` is a dataset object, optional if FLAG_FULL_MEM is not set.
+    ///
+    /// The VM runs the algorithm the cache and dataset were created for, which must be the same.
     pub fn new(
         flags: RandomXFlag,
         cache: Option<&RandomXCache>,
@@ -297,164 +515,269 @@ impl RandomXVM {
         }
 
         if cache.is_none() && !is_full_mem {
-            return Err(RandomXError::FlagConfigError);
+            return Err(RandomXError::InvalidFlags {
+                flags,
+                reason: "a cache is required without FLAG_FULL_MEM",
+            });
         }
 
         if dataset.is_none() && is_full_mem {
-            return Err(RandomXError::FlagConfigError);
+            return Err(RandomXError::InvalidFlags {
+                flags,
+                reason: "a dataset is required with FLAG_FULL_MEM",
+            });
         }
 
+        let algorithm = match (cache, dataset) {
+            (Some(stash), Some(data)) if stash.algorithm() != data.algorithm() => {
+                return Err(RandomXError::InvalidParameter(
+                    "cache and dataset are for different algorithms",
+                ));
+            }
+            (Some(stash), _) => stash.algorithm(),
+            (None, Some(data)) => data.algorithm(),
+            (None, None) => Algorithm::Rx0,
+        };
+        let create_vm = algorithm.ffi().create_vm;
+
         match cache {
             Some(stash) => match dataset {
                 Some(data) => unsafe {
-                    test = randomx_create_vm(flags.bits, stash.cache, data.dataset)
-                },
-                None => unsafe {
-                    test = randomx_create_vm(flags.bits, stash.cache, ptr::null_mut())
+                    test = create_vm(flags.bits, stash.as_ptr(), data.as_ptr())
                 },
+                None => unsafe { test = create_vm(flags.bits, stash.as_ptr(), ptr::null_mut()) },
             },
             None => match dataset {
                 Some(data) => unsafe {
-                    test = randomx_create_vm(flags.bits, ptr::null_mut(), data.dataset)
+                    test = create_vm(flags.bits, ptr::null_mut(), data.as_ptr())
                 },
                 None => test = ptr::null_mut(),
             },
         }
 
         if test.is_null() {
-            return Err(RandomXError::CreationError);
+            return Err(RandomXError::allocation(Allocation::Vm, flags));
         }
 
-        let result = RandomXVM { vm: test, flags };
+        let result = RandomXVM {
+            vm: test,
+            flags,
+            algorithm,
+            linked_cache: if is_full_mem { None } else { cache.cloned() },
+            linked_dataset: dataset.cloned(),
+        };
         Ok(result)
     }
 
+    /// Returns the algorithm the VM runs.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn api(&self) -> &'static Api {
+        self.algorithm.ffi()
+    }
+
     /// Re-initializes the `VM` with a new cache that was initialised without
-    /// RandomXFlag::FLAG_FULL_MEM.
-    pub fn reinit_cache(&self, cache: &RandomXCache) -> Result<(), RandomXError> {
+    /// RandomXFlag::FLAG_FULL_MEM. The previous cache is released if nothing else uses it.
+    pub fn reinit_cache(&mut self, cache: &RandomXCache) -> Result<(), RandomXError> {
         if self.flags & RandomXFlag::FLAG_FULL_MEM == RandomXFlag::FLAG_FULL_MEM {
-            return Err(RandomXError::FlagConfigError);
+            return Err(RandomXError::InvalidFlags {
+                flags: self.flags,
+                reason: "a FLAG_FULL_MEM vm hashes over a dataset, not a cache",
+            });
+        }
+        if cache.algorithm() != self.algorithm {
+            return Err(RandomXError::InvalidParameter(
+                "cache is for a different algorithm than the vm",
+            ));
         }
         //no way to check if this fails, c code does not return anything
         unsafe {
-            randomx_vm_set_cache(self.vm, cache.cache);
+            (self.api().vm_set_cache)(self.vm, cache.as_ptr());
         }
+        self.linked_cache = Some(cache.clone());
         Ok(())
     }
 
     /// Re-initializes the `VM` with a new dataset that was initialised with
-    /// RandomXFlag::FLAG_FULL_MEM.
-    pub fn reinit_dataset(&self, dataset: &RandomXDataset) -> Result<(), RandomXError> {
+    /// RandomXFlag::FLAG_FULL_MEM. The previous dataset is released if nothing else uses it.
+    pub fn reinit_dataset(&mut self, dataset: &RandomXDataset) -> Result<(), RandomXError> {
         if self.flags & RandomXFlag::FLAG_FULL_MEM != RandomXFlag::FLAG_FULL_MEM {
-            return Err(RandomXError::FlagConfigError);
+            return Err(RandomXError::InvalidFlags {
+                flags: self.flags,
+                reason: "a dataset needs a FLAG_FULL_MEM vm",
+            });
+        }
+        if dataset.algorithm() != self.algorithm {
+            return Err(RandomXError::InvalidParameter(
+                "dataset is for a different algorithm than the vm",
+            ));
         }
         //no way to check if this fails, c code does not return anything
         unsafe {
-            randomx_vm_set_dataset(self.vm, dataset.dataset);
+            (self.api().vm_set_dataset)(self.vm, dataset.as_ptr());
         }
+        self.linked_cache = None;
+        self.linked_dataset = Some(dataset.clone());
         Ok(())
     }
 
     /// Calculates a RandomX hash value and returns it, error on failure.
     ///
     /// `input` is a sequence of u8 to be hashed.
-    pub fn calculate_hash(&self, input: &[u8]) -> Result<[u8; 32], RandomXError> {
+    pub fn calculate_hash(&mut self, input: &[u8]) -> Result<[u8; 32], RandomXError> {
         if input.is_empty() {
-            return Err(RandomXError::ParameterError);
+            return Err(RandomXError::EmptyInput);
         };
         let size_input = input.len() as usize;
         let input_ptr = input.as_ptr() as *mut c_void;
-        let arr = [0; RANDOMX_HASH_SIZE as usize];
-        let output_ptr = arr.as_ptr() as *mut c_void;
+        let mut arr = [0; RANDOMX_HASH_SIZE as usize];
+        let output_ptr = arr.as_mut_ptr() as *mut c_void;
+        //no way to check if this fails, c code does not return anything
         unsafe {
-            randomx_calculate_hash(self.vm, input_ptr, size_input, output_ptr);
-        }
-        // if this failed, arr should still be empty
-        if arr == [0; RANDOMX_HASH_SIZE as usize] {
-            return Err(RandomXError::Other);
+            (self.api().calculate_hash)(self.vm, input_ptr, size_input, output_ptr);
         }
-        let result = arr;
-        Ok(result)
+        Ok(arr)
     }
 
     /// Calculates hashes from a set of inputs.
     ///
     /// `input` is an array of a sequence of u8 to be hashed.
-    #[allow(clippy::needless_range_loop)] // Range loop is not only for indexing `input`
-    pub fn calculate_hash_set(&self, input: &[&[u8]]) -> Result<Vec<[u8; 32]>, RandomXError> {
-        if input.is_empty() {
-            // Empty set
-            return Err(RandomXError::ParameterError);
+    pub fn calculate_hash_set(&mut self, input: &[&[u8]]) -> Result<Vec<[u8; 32]>, RandomXError> {
+        if input.is_empty() || input.iter().any(|x| x.is_empty()) {
+            return Err(RandomXError::EmptyInput);
         }
 
-        let mut result = Vec::new();
         // For single input
         if input.len() == 1 {
-            let hash_result = self.calculate_hash(input[0]);
-            return match hash_result {
-                Ok(hash) => {
-                    result.push(hash);
-                    Ok(result)
-                }
-                Err(e) => Err(e),
-            };
+            return Ok(vec![self.calculate_hash(input[0])?]);
         }
 
-        // For multiple inputs
-        let mut output_ptr: *mut c_void = ptr::null_mut();
-        let arr = [0; RANDOMX_HASH_SIZE as usize];
-
-        // Not len() as last iteration assigns final hash
-        let iterations = input.len() + 1;
-        for i in 0..iterations {
-            if i != iterations - 1 {
-                if input[i].is_empty() {
-                    // Stop calculations
-                    if arr != [0; RANDOMX_HASH_SIZE as usize] {
-                        // Complete what was started
-                        unsafe {
-                            randomx_calculate_hash_last(self.vm, output_ptr);
-                        }
-                    }
-                    return Err(RandomXError::ParameterError);
-                };
-                let size_input = input[i].len() as usize;
-                let input_ptr = input[i].as_ptr() as *mut c_void;
-                output_ptr = arr.as_ptr() as *mut c_void;
-                if i == 0 {
-                    // For first iteration
-                    unsafe {
-                        randomx_calculate_hash_first(self.vm, input_ptr, size_input);
-                    }
-                } else {
-                    unsafe {
-                        // For every other iteration
-                        randomx_calculate_hash_next(self.vm, input_ptr, size_input, output_ptr);
-                    }
-                }
-            } else {
-                // For last iteration
-                unsafe {
-                    randomx_calculate_hash_last(self.vm, output_ptr);
-                }
+        // For multiple inputs, each call outputs the hash of the input passed to the previous one
+        let mut result = Vec::with_capacity(input.len());
+        unsafe {
+            (self.api().calculate_hash_first)(
+                self.vm,
+                input[0].as_ptr() as *const c_void,
+                input[0].len(),
+            );
+        }
+        for next in &input[1..] {
+            let mut output = [0u8; RANDOMX_HASH_SIZE as usize];
+            unsafe {
+                (self.api().calculate_hash_next)(
+                    self.vm,
+                    next.as_ptr() as *const c_void,
+                    next.len(),
+                    output.as_mut_ptr() as *mut c_void,
+                );
             }
+            result.push(output);
+        }
+        let mut output = [0u8; RANDOMX_HASH_SIZE as usize];
+        unsafe {
+            (self.api().calculate_hash_last)(self.vm, output.as_mut_ptr() as *mut c_void);
+        }
+        result.push(output);
+        Ok(result)
+    }
 
-            if i != 0 {
-                // First hash is only available in 2nd iteration
-                if arr == [0; RANDOMX_HASH_SIZE as usize] {
-                    return Err(RandomXError::Other);
+    /// Hashes `blob` once for each nonce in `nonces`, returning an iterator over the nonces and
+    /// their hashes, error on failure.
+    ///
+    /// Each nonce is written into `blob` as 4 little-endian bytes at `nonce_offset`. Hashing is
+    /// pipelined: the hash of one nonce is finished while the next one is started, so `nonces` is
+    /// advanced one step ahead of the iterator. The iterator can be dropped at any point.
+    pub fn hash_nonces<'a, I>(
+        &'a mut self,
+        blob: &'a mut [u8],
+        nonce_offset: usize,
+        nonces: I,
+    ) -> Result<NonceHashes<'a, I::IntoIter>, RandomXError>
+    where
+        I: IntoIterator<Item = u32>,
+    {
+        if blob.len() < nonce_offset + 4 {
+            return Err(RandomXError::InvalidParameter(
+                "nonce does not fit in the blob",
+            ));
+        }
+        let mut nonces = nonces.into_iter();
+        let pending = nonces.next();
+        let mut hashes = NonceHashes {
+            vm: self,
+            blob,
+            nonce_offset,
+            nonces,
+            pending,
+        };
+        if let Some(nonce) = pending {
+            hashes.set_nonce(nonce);
+            unsafe {
+                (hashes.vm.api().calculate_hash_first)(
+                    hashes.vm.vm,
+                    hashes.blob.as_ptr() as *const c_void,
+                    hashes.blob.len(),
+                );
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+/// Iterator over `(nonce, hash)` pairs, see `RandomXVM::hash_nonces`.
+pub struct NonceHashes<'a, I> {
+    vm: &'a mut RandomXVM,
+    blob: &'a mut [u8],
+    nonce_offset: usize,
+    nonces: I,
+    /// Nonce whose hash has been started but not output yet.
+    pending: Option<u32>,
+}
+
+impl<I> NonceHashes<'_, I> {
+    fn set_nonce(&mut self, nonce: u32) {
+        self.blob[self.nonce_offset..self.nonce_offset + 4].copy_from_slice(&nonce.to_le_bytes());
+    }
+}
+
+impl<I: Iterator<Item = u32>> Iterator for NonceHashes<'_, I> {
+    type Item = (u32, [u8; 32]);
+
+    fn next(&mut self) -> Option<(u32, [u8; 32])> {
+        let nonce = self.pending?;
+        let mut output = [0u8; RANDOMX_HASH_SIZE as usize];
+        let output_ptr = output.as_mut_ptr() as *mut c_void;
+        self.pending = self.nonces.next();
+        match self.pending {
+            Some(next) => {
+                self.set_nonce(next);
+                unsafe {
+                    (self.vm.api().calculate_hash_next)(
+                        self.vm.vm,
+                        self.blob.as_ptr() as *const c_void,
+                        self.blob.len(),
+                        output_ptr,
+                    );
                 }
-                let output: Vec<u8> = arr.to_vec();
-                result.push([0; 32]);
             }
+            None => unsafe {
+                (self.vm.api().calculate_hash_last)(self.vm.vm, output_ptr);
+            },
         }
-        Ok(result)
+        Some((nonce, output))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{RandomXCache, RandomXDataset, RandomXFlag, RandomXVM};
+    use crate::bindings::RANDOMX_DATASET_ITEM_SIZE;
+    use crate::{
+        Algorithm, Allocation, RandomXCache, RandomXDataset, RandomXError, RandomXFlag, RandomXVM,
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
 
     #[test]
     fn lib_alloc_cache() {
@@ -480,6 +803,79 @@ mod tests {
         drop(cache);
     }
 
+    #[test]
+    fn lib_alloc_dataset_parallel() {
+        let flags = RandomXFlag::default();
+        let key = "Key";
+        let input = "Input";
+        let cache = RandomXCache::new(flags, key.as_bytes()).unwrap();
+        let total = AtomicU64::new(0);
+        let dataset = RandomXDataset::new_parallel(flags, &cache, 0, 4, |done, count| {
+            assert!(done <= count);
+            total.store(count, Ordering::Relaxed);
+        })
+        .unwrap();
+        assert_eq!(total.load(Ordering::Relaxed), dataset.count().unwrap());
+        let serial = RandomXDataset::new(flags, &cache, 0).unwrap();
+        let flags2 = flags | RandomXFlag::FLAG_FULL_MEM;
+        let mut vm1 = RandomXVM::new(flags2, None, Some(&dataset)).unwrap();
+        let mut vm2 = RandomXVM::new(flags2, None, Some(&serial)).unwrap();
+        assert_eq!(
+            vm1.calculate_hash(input.as_bytes()).unwrap(),
+            vm2.calculate_hash(input.as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn lib_alloc_dataset_bad_start() {
+        let flags = RandomXFlag::default();
+        let cache = RandomXCache::new(flags, "Key".as_bytes()).unwrap();
+        let count = unsafe { crate::bindings::randomx_dataset_item_count() };
+        assert!(RandomXDataset::new(flags, &cache, count).is_err());
+        let dataset = RandomXDataset::new_with(flags, &cache, 0, 2, &|_, _| {}, |mut slices| {
+            slices.pop().unwrap().init();
+        });
+        match dataset {
+            Err(RandomXError::DatasetIncomplete { initialized, total }) => {
+                assert_eq!(total, count as u64);
+                assert!(initialized < total);
+            }
+            _ => panic!("expected an incomplete dataset"),
+        }
+    }
+
+    #[test]
+    fn lib_errors() {
+        let flags = RandomXFlag::default();
+        assert_eq!(
+            RandomXCache::new(flags, &[]).unwrap_err(),
+            RandomXError::EmptyKey
+        );
+        let cache = RandomXCache::new(flags, "Key".as_bytes()).unwrap();
+        let err = RandomXVM::new(RandomXFlag::FLAG_FULL_MEM, Some(&cache), None).unwrap_err();
+        match err {
+            RandomXError::InvalidFlags { flags, .. } => {
+                assert_eq!(flags, RandomXFlag::FLAG_FULL_MEM)
+            }
+            _ => panic!("expected invalid flags"),
+        }
+        assert!(err.to_string().contains("FLAG_FULL_MEM"));
+        let mut vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+        assert_eq!(
+            vm.calculate_hash(&[]).unwrap_err(),
+            RandomXError::EmptyInput
+        );
+
+        let err = RandomXError::allocation(Allocation::Dataset, RandomXFlag::FLAG_LARGE_PAGES);
+        assert!(err.is_allocation());
+        assert_eq!(
+            err.to_string(),
+            "failed to allocate RandomX dataset in large pages (flags: FLAG_LARGE_PAGES)"
+        );
+        let err: &dyn std::error::Error = &err;
+        assert!(err.source().is_none());
+    }
+
     #[test]
     fn lib_alloc_vm() {
         let flags = RandomXFlag::default();
@@ -500,6 +896,51 @@ mod tests {
         drop(vm);
     }
 
+    #[test]
+    fn lib_vm_outlives_cache_and_dataset() {
+        let flags = RandomXFlag::default();
+        let key = "Key";
+        let input = "Input";
+        let cache = RandomXCache::new(flags, key.as_bytes()).unwrap();
+        let mut light_vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+        let dataset = RandomXDataset::new(flags, &cache, 0).unwrap();
+        let flags2 = flags | RandomXFlag::FLAG_FULL_MEM;
+        let mut full_vm = RandomXVM::new(flags2, None, Some(&dataset)).unwrap();
+        drop(dataset);
+        drop(cache);
+        let hash1 = light_vm.calculate_hash(input.as_bytes()).unwrap();
+        let hash2 = full_vm.calculate_hash(input.as_bytes()).unwrap();
+        assert_eq!(hash1, hash2);
+    }
+
+    #[test]
+    fn lib_full_vm_releases_cache() {
+        let flags = RandomXFlag::default();
+        let full = flags | RandomXFlag::FLAG_FULL_MEM;
+        let cache = RandomXCache::new(flags, b"Key").unwrap();
+        let dataset = RandomXDataset::new(flags, &cache, 0).unwrap();
+        let mut vm = RandomXVM::new(full, Some(&cache), Some(&dataset)).unwrap();
+        let (old_cache, old_dataset) =
+            (Arc::downgrade(&cache.inner), Arc::downgrade(&dataset.inner));
+        drop((cache, dataset));
+        assert_eq!(old_cache.strong_count(), 0);
+        assert_eq!(old_dataset.strong_count(), 1);
+
+        let cache = RandomXCache::new(flags, b"Other key").unwrap();
+        vm.reinit_dataset(&RandomXDataset::new(flags, &cache, 0).unwrap())
+            .unwrap();
+        assert_eq!(old_dataset.strong_count(), 0);
+    }
+
+    #[test]
+    fn lib_vm_is_send() {
+        fn assert_send<T: Send>() {}
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send::<RandomXVM>();
+        assert_send_sync::<RandomXCache>();
+        assert_send_sync::<RandomXDataset>();
+    }
+
     #[test]
     fn lib_dataset_memory() {
         let flags = RandomXFlag::default();
@@ -510,8 +951,11 @@ mod tests {
         if memory.len() == 0 {
             panic!("Failed to get dataset memory");
         }
+        let size = dataset.count().unwrap() as usize * RANDOMX_DATASET_ITEM_SIZE as usize;
+        assert_eq!(memory.len(), size);
         let vec = vec![0u8; memory.len() as usize];
         assert_ne!(memory, vec);
+        assert_eq!(dataset.as_bytes().unwrap(), &memory[..]);
         drop(dataset);
         drop(cache);
     }
@@ -523,9 +967,9 @@ mod tests {
         let key = "Key";
         let input = "Input";
         let cache1 = RandomXCache::new(flags, key.as_bytes()).unwrap();
-        let vm1 = RandomXVM::new(flags, Some(&cache1), None).unwrap();
+        let mut vm1 = RandomXVM::new(flags, Some(&cache1), None).unwrap();
         let hash1 = vm1.calculate_hash(input.as_bytes()).expect("no data");
-        let vec = vec![0u8; hash1.len() as usize];
+        let vec = [0u8; 32];
         assert_ne!(hash1, vec);
         let reinit_cache = vm1.reinit_cache(&cache1);
         assert_eq!(reinit_cache.is_ok(), true);
@@ -534,13 +978,13 @@ mod tests {
         assert_eq!(hash1, hash2);
 
         let cache2 = RandomXCache::new(flags, key.as_bytes()).unwrap();
-        let vm2 = RandomXVM::new(flags, Some(&cache2), None).unwrap();
+        let mut vm2 = RandomXVM::new(flags, Some(&cache2), None).unwrap();
         let hash3 = vm2.calculate_hash(input.as_bytes()).expect("no data");
         assert_eq!(hash2, hash3);
 
         let cache3 = RandomXCache::new(flags, key.as_bytes()).unwrap();
         let dataset3 = RandomXDataset::new(flags, &cache3, 0).unwrap();
-        let vm3 = RandomXVM::new(flags2, None, Some(&dataset3)).unwrap();
+        let mut vm3 = RandomXVM::new(flags2, None, Some(&dataset3)).unwrap();
         let hash4 = vm3.calculate_hash(input.as_bytes()).expect("no data");
         assert_ne!(hash3, vec);
         let reinit_dataset = vm3.reinit_dataset(&dataset3);
@@ -575,13 +1019,13 @@ mod tests {
         inputs.push("Input 2".as_bytes());
         inputs.push("Inputs 3".as_bytes());
         let cache = RandomXCache::new(flags, key.as_bytes()).unwrap();
-        let vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+        let mut vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
         let hashes = vm.calculate_hash_set(inputs.as_slice()).expect("no data");
         assert_eq!(inputs.len(), hashes.len());
-        let mut prev_hash = Vec::new();
+        let mut prev_hash = [0u8; 32];
         let mut i = 0;
         for hash in hashes {
-            let vec = vec![0u8; hash.len() as usize];
+            let vec = [0u8; 32];
             assert_ne!(hash, vec);
             assert_ne!(hash, prev_hash);
             let compare = vm.calculate_hash(inputs[i]).unwrap(); //sanity check
@@ -593,6 +1037,37 @@ mod tests {
         drop(vm);
     }
 
+    #[test]
+    fn lib_hash_nonces() {
+        let flags = RandomXFlag::default();
+        let cache = RandomXCache::new(flags, "Key".as_bytes()).unwrap();
+        let mut vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+        let mut blob = [7u8; 76];
+        let hashes: Vec<_> = vm
+            .hash_nonces(&mut blob, 39, vec![0u32, 1, 0xdead_beef])
+            .unwrap()
+            .collect();
+        assert_eq!(hashes.len(), 3);
+        for (nonce, hash) in hashes {
+            let mut input = [7u8; 76];
+            input[39..43].copy_from_slice(&nonce.to_le_bytes());
+            assert_eq!(hash, vm.calculate_hash(&input).unwrap());
+        }
+
+        // stopping early leaves the vm usable
+        let first = vm.hash_nonces(&mut blob, 39, 5u32..).unwrap().next();
+        let mut input = [7u8; 76];
+        input[39..43].copy_from_slice(&5u32.to_le_bytes());
+        assert_eq!(first.unwrap().1, vm.calculate_hash(&input).unwrap());
+
+        assert!(vm
+            .hash_nonces(&mut blob, 39, None)
+            .unwrap()
+            .next()
+            .is_none());
+        assert!(vm.hash_nonces(&mut blob, 73, Some(0)).is_err());
+    }
+
     #[test]
     fn lib_calculate_hash_is_consistent() {
         let flags = RandomXFlag::get_recommended_flags();
@@ -600,7 +1075,7 @@ mod tests {
         let input = "Input";
         let cache = RandomXCache::new(flags, key.as_bytes()).unwrap();
         let dataset = RandomXDataset::new(flags, &cache, 0).unwrap();
-        let vm = RandomXVM::new(flags, Some(&cache), Some(&dataset)).unwrap();
+        let mut vm = RandomXVM::new(flags, Some(&cache), Some(&dataset)).unwrap();
         let hash = vm.calculate_hash(input.as_bytes()).expect("no data");
         assert_eq!(
             hash,
@@ -615,7 +1090,7 @@ mod tests {
 
         let cache1 = RandomXCache::new(flags, key.as_bytes()).unwrap();
         let dataset1 = RandomXDataset::new(flags, &cache1, 0).unwrap();
-        let vm1 = RandomXVM::new(flags, Some(&cache1), Some(&dataset1)).unwrap();
+        let mut vm1 = RandomXVM::new(flags, Some(&cache1), Some(&dataset1)).unwrap();
         let hash1 = vm1.calculate_hash(input.as_bytes()).expect("no data");
         assert_eq!(
             hash1,
@@ -628,4 +1103,47 @@ mod tests {
         drop(dataset1);
         drop(cache1);
     }
+
+    #[test]
+    fn lib_algorithms() {
+        let flags = RandomXFlag::default();
+        let key = "Key".as_bytes();
+        let cache = RandomXCache::new(flags, key).unwrap();
+        assert_eq!(cache.algorithm(), Algorithm::Rx0);
+        let vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+        assert_eq!(vm.algorithm(), Algorithm::Rx0);
+
+        for &algorithm in Algorithm::ALL.iter() {
+            match RandomXCache::with_algorithm(algorithm, flags, key) {
+                Ok(cache) => {
+                    assert!(algorithm.is_available());
+                    assert_eq!(cache.algorithm(), algorithm);
+                    let mut vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+                    assert_eq!(vm.algorithm(), algorithm);
+                    vm.calculate_hash("Input".as_bytes()).unwrap();
+                }
+                Err(err) => {
+                    assert!(!algorithm.is_available());
+                    assert_eq!(err, RandomXError::UnsupportedAlgorithm(algorithm));
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "rx-wow")]
+    #[test]
+    fn lib_algorithms_differ() {
+        let flags = RandomXFlag::default();
+        let key = "Key".as_bytes();
+        let input = "Input".as_bytes();
+        let cache = RandomXCache::new(flags, key).unwrap();
+        let wow_cache = RandomXCache::with_algorithm(Algorithm::RxWow, flags, key).unwrap();
+        let mut vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+        let mut wow_vm = RandomXVM::new(flags, Some(&wow_cache), None).unwrap();
+        assert_ne!(
+            vm.calculate_hash(input).unwrap(),
+            wow_vm.calculate_hash(input).unwrap()
+        );
+        assert!(vm.reinit_cache(&wow_cache).is_err());
+    }
 }
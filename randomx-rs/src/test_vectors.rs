@@ -0,0 +1,183 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Known-answer tests from the RandomX reference implementation (`src/tests/tests.cpp`).
+
+use crate::{Algorithm, RandomXCache, RandomXError, RandomXFlag, RandomXVM};
+
+/// A key, an input and the RandomX hash of the input with a cache initialized from the key.
+#[derive(Debug)]
+pub struct TestVector {
+    pub key: &'static [u8],
+    pub input: &'static [u8],
+    pub hash: [u8; 32],
+}
+
+/// The reference test vectors, grouped by key.
+pub const TEST_VECTORS: [TestVector; 5] = [
+    TestVector {
+        key: b"test key 000",
+        input: b"This is a test",
+        hash: [
+            0x63, 0x91, 0x83, 0xaa, 0xe1, 0xbf, 0x4c, 0x9a, 0x35, 0x88, 0x4c, 0xb4, 0x6b, 0x09,
+            0xca, 0xd9, 0x17, 0x5f, 0x04, 0xef, 0xd7, 0x68, 0x4e, 0x72, 0x62, 0xa0, 0xac, 0x1c,
+            0x2f, 0x0b, 0x4e, 0x3f,
+        ],
+    },
+    TestVector {
+        key: b"test key 000",
+        input: b"Lorem ipsum dolor sit amet",
+        hash: [
+            0x30, 0x0a, 0x0a, 0xdb, 0x47, 0x60, 0x3d, 0xed, 0xb4, 0x22, 0x28, 0xcc, 0xb2, 0xb2,
+            0x11, 0x10, 0x4f, 0x4d, 0xa4, 0x5a, 0xf7, 0x09, 0xcd, 0x75, 0x47, 0xcd, 0x04, 0x9e,
+            0x94, 0x89, 0xc9, 0x69,
+        ],
+    },
+    TestVector {
+        key: b"test key 000",
+        input: b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua",
+        hash: [
+            0xc3, 0x6d, 0x4e, 0xd4, 0x19, 0x1e, 0x61, 0x73, 0x09, 0x86, 0x7e, 0xd6, 0x6a, 0x44,
+            0x3b, 0xe4, 0x07, 0x50, 0x14, 0xe2, 0xb0, 0x61, 0xbc, 0xda, 0xf9, 0xce, 0x7b, 0x72,
+            0x1d, 0x2b, 0x77, 0xa8,
+        ],
+    },
+    TestVector {
+        key: b"test key 001",
+        input: b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua",
+        hash: [
+            0xe9, 0xff, 0x45, 0x03, 0x20, 0x1c, 0x0c, 0x2c, 0xca, 0x26, 0xd2, 0x85, 0xc9, 0x3a,
+            0xe8, 0x83, 0xf9, 0xb1, 0xd3, 0x0c, 0x9e, 0xb2, 0x40, 0xb8, 0x20, 0x75, 0x6f, 0x2d,
+            0x5a, 0x79, 0x05, 0xfc,
+        ],
+    },
+    TestVector {
+        key: b"test key 001",
+        input: &[
+            0x0b, 0x0b, 0x98, 0xbe, 0xa7, 0xe8, 0x05, 0xe0, 0x01, 0x0a, 0x21, 0x26, 0xd2, 0x87,
+            0xa2, 0xa0, 0xcc, 0x83, 0x3d, 0x31, 0x2c, 0xb7, 0x86, 0x38, 0x5a, 0x7c, 0x2f, 0x9d,
+            0xe6, 0x9d, 0x25, 0x53, 0x7f, 0x58, 0x4a, 0x9b, 0xc9, 0x97, 0x7b, 0x00, 0x00, 0x00,
+            0x00, 0x66, 0x6f, 0xd8, 0x75, 0x3b, 0xf6, 0x1a, 0x86, 0x31, 0xf1, 0x29, 0x84, 0xe3,
+            0xfd, 0x44, 0xf4, 0x01, 0x4e, 0xca, 0x62, 0x92, 0x76, 0x81, 0x7b, 0x56, 0xf3, 0x2e,
+            0x9b, 0x68, 0xbd, 0x82, 0xf4, 0x16,
+        ],
+        hash: [
+            0xc5, 0x64, 0x14, 0x12, 0x1a, 0xcd, 0xa1, 0x71, 0x3c, 0x2f, 0x2a, 0x81, 0x9d, 0x8a,
+            0xe3, 0x8a, 0xed, 0x7c, 0x80, 0xc3, 0x5c, 0x2a, 0x76, 0x92, 0x98, 0xd3, 0x4f, 0x03,
+            0x83, 0x3c, 0xd5, 0xf1,
+        ],
+    },
+];
+
+/// Hashes every test vector in light mode with `flags` (less FLAG_FULL_MEM, as a dataset for each
+/// key would take too long to initialize) and compares the result against the expected hash,
+/// error on failure or on the first mismatch.
+pub fn self_test(flags: RandomXFlag) -> Result<(), RandomXError> {
+    let flags = flags - RandomXFlag::FLAG_FULL_MEM;
+    let hashes = hash_vectors(Algorithm::Rx0, flags)?;
+    match TEST_VECTORS
+        .iter()
+        .zip(&hashes)
+        .position(|(vector, hash)| vector.hash != *hash)
+    {
+        Some(index) => Err(RandomXError::SelfTestFailed { flags, index }),
+        None => Ok(()),
+    }
+}
+
+/// Checks `flags` for `algorithm` in light mode, as `self_test` does for rx/0. The forks have no
+/// test vectors, so each test input is hashed under its key with `flags` and with the portable
+/// implementation (interpreter, software AES, reference Argon2), and the hashes must match.
+pub fn self_test_algorithm(algorithm: Algorithm, flags: RandomXFlag) -> Result<(), RandomXError> {
+    if algorithm == Algorithm::Rx0 {
+        return self_test(flags);
+    }
+    let flags = flags - RandomXFlag::FLAG_FULL_MEM;
+    let expected = hash_vectors(algorithm, RandomXFlag::FLAG_DEFAULT)?;
+    let hashes = hash_vectors(algorithm, flags)?;
+    match expected.iter().zip(&hashes).position(|(a, b)| a != b) {
+        Some(index) => Err(RandomXError::SelfTestFailed { flags, index }),
+        None => Ok(()),
+    }
+}
+
+/// Hashes the input of every test vector under its key with `algorithm` in light mode.
+fn hash_vectors(algorithm: Algorithm, flags: RandomXFlag) -> Result<Vec<[u8; 32]>, RandomXError> {
+    let mut hashes = Vec::with_capacity(TEST_VECTORS.len());
+    let mut current: Option<(&[u8], RandomXVM)> = None;
+    for vector in TEST_VECTORS.iter() {
+        match &mut current {
+            Some((key, _)) if *key == vector.key => (),
+            Some((key, vm)) => {
+                vm.reinit_cache(&RandomXCache::with_algorithm(algorithm, flags, vector.key)?)?;
+                *key = vector.key;
+            }
+            None => {
+                let cache = RandomXCache::with_algorithm(algorithm, flags, vector.key)?;
+                current = Some((vector.key, RandomXVM::new(flags, Some(&cache), None)?));
+            }
+        }
+        let (_, vm) = current.as_mut().unwrap();
+        hashes.push(vm.calculate_hash(vector.input)?);
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_vectors::{self_test, self_test_algorithm, TEST_VECTORS};
+    use crate::{Algorithm, RandomXCache, RandomXDataset, RandomXFlag, RandomXVM};
+
+    #[test]
+    fn test_vectors_light() {
+        for vector in TEST_VECTORS.iter() {
+            let flags = RandomXFlag::default();
+            let cache = RandomXCache::new(flags, vector.key).unwrap();
+            let mut vm = RandomXVM::new(flags, Some(&cache), None).unwrap();
+            assert_eq!(vm.calculate_hash(vector.input).unwrap(), vector.hash);
+        }
+    }
+
+    #[test]
+    fn test_vectors_full_mem() {
+        let flags = RandomXFlag::get_recommended_flags() | RandomXFlag::FLAG_FULL_MEM;
+        let vector = &TEST_VECTORS[0];
+        let cache = RandomXCache::new(flags, vector.key).unwrap();
+        let dataset = RandomXDataset::new(flags, &cache, 0).unwrap();
+        let mut vm = RandomXVM::new(flags, None, Some(&dataset)).unwrap();
+        assert_eq!(vm.calculate_hash(vector.input).unwrap(), vector.hash);
+    }
+
+    #[test]
+    fn test_self_test() {
+        self_test(RandomXFlag::default()).unwrap();
+        self_test(RandomXFlag::get_recommended_flags()).unwrap();
+    }
+
+    #[test]
+    fn test_self_test_algorithm() {
+        let flags = RandomXFlag::get_recommended_flags();
+        for &algorithm in Algorithm::available().iter() {
+            self_test_algorithm(algorithm, flags).unwrap();
+        }
+    }
+}
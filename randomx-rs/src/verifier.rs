@@ -0,0 +1,222 @@
+// Copyright 2019. The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{Algorithm, RandomXCache, RandomXError, RandomXFlag, RandomXVM};
+use std::sync::Mutex;
+
+/// Checks RandomX hashes for any number of seeds, from any number of threads.
+///
+/// Hashes are computed in light mode. The caches for the most recently used seeds are kept, up to
+/// `capacity`, so a seed only needs a new cache when it was evicted. VMs are kept in a pool and
+/// handed to one thread at a time; there are as many as there have been concurrent calls. A VM
+/// keeps the cache it is set to alive, so VMs set to an evicted seed are dropped, not pooled.
+#[derive(Debug)]
+pub struct Verifier {
+    algorithm: Algorithm,
+    flags: RandomXFlag,
+    capacity: usize,
+    /// Caches by seed, least recently used first.
+    caches: Mutex<Vec<(Vec<u8>, RandomXCache)>>,
+    /// Idle VMs, with the seed of the cache each one is set to, which is always in `caches`.
+    vms: Mutex<Vec<(Vec<u8>, RandomXVM)>>,
+}
+
+impl Verifier {
+    /// Creates a verifier keeping the caches for up to `capacity` seeds.
+    ///
+    /// `flags` is any combination of the following flags, FLAG_FULL_MEM is ignored:
+    /// * FLAG_LARGE_PAGES
+    /// * FLAG_HARD_AES
+    /// * FLAG_JIT
+    /// * FLAG_SECURE
+    /// * FLAG_ARGON2_SSSE3
+    /// * FLAG_ARGON2_AVX2
+    pub fn new(flags: RandomXFlag, capacity: usize) -> Verifier {
+        Verifier::with_algorithm(Algorithm::Rx0, flags, capacity)
+    }
+
+    /// Creates a verifier for `algorithm`, with `flags` and `capacity` as in `new`. If the library
+    /// was not built with `algorithm`, every hash fails with `UnsupportedAlgorithm`.
+    pub fn with_algorithm(algorithm: Algorithm, flags: RandomXFlag, capacity: usize) -> Verifier {
+        Verifier {
+            algorithm,
+            flags: flags - RandomXFlag::FLAG_FULL_MEM,
+            capacity: capacity.max(1),
+            caches: Mutex::new(Vec::new()),
+            vms: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the algorithm hashes are checked with.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Calculates the RandomX hash of `input` with the cache for `seed`, error on failure.
+    pub fn hash(&self, seed: &[u8], input: &[u8]) -> Result<[u8; 32], RandomXError> {
+        let cache = self.cache(seed)?;
+        let mut vm = self.vm(seed, &cache)?;
+        let result = vm.1.calculate_hash(input);
+        self.release(vm);
+        result
+    }
+
+    /// Returns whether the RandomX hash of `input` with the cache for `seed` is `expected`.
+    pub fn verify(&self, seed: &[u8], input: &[u8], expected: &[u8]) -> bool {
+        match self.hash(seed, input) {
+            Ok(hash) => hash[..] == *expected,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the cache for `seed`, creating it, and evicting the least recently used one, if
+    /// it is not kept.
+    fn cache(&self, seed: &[u8]) -> Result<RandomXCache, RandomXError> {
+        {
+            let mut caches = self.caches.lock().unwrap();
+            if let Some(i) = caches.iter().position(|(s, _)| s.as_slice() == seed) {
+                let entry = caches.remove(i);
+                let cache = entry.1.clone();
+                caches.push(entry);
+                return Ok(cache);
+            }
+        }
+        // initialize outside the lock, so other seeds can be hashed meanwhile
+        let cache = RandomXCache::with_algorithm(self.algorithm, self.flags, seed)?;
+        let mut caches = self.caches.lock().unwrap();
+        if !caches.iter().any(|(s, _)| s.as_slice() == seed) {
+            if caches.len() == self.capacity {
+                let (evicted, _) = caches.remove(0);
+                self.vms.lock().unwrap().retain(|(s, _)| *s != evicted);
+            }
+            caches.push((seed.to_vec(), cache.clone()));
+        }
+        Ok(cache)
+    }
+
+    /// Takes an idle VM, preferably one already set to the cache for `seed`, or creates one.
+    fn vm(&self, seed: &[u8], cache: &RandomXCache) -> Result<(Vec<u8>, RandomXVM), RandomXError> {
+        let idle = {
+            let mut vms = self.vms.lock().unwrap();
+            match vms.iter().position(|(s, _)| s.as_slice() == seed) {
+                Some(i) => return Ok(vms.swap_remove(i)),
+                None => vms.pop(),
+            }
+        };
+        match idle {
+            Some((_, mut vm)) => {
+                vm.reinit_cache(cache)?;
+                Ok((seed.to_vec(), vm))
+            }
+            None => Ok((
+                seed.to_vec(),
+                RandomXVM::new(self.flags, Some(cache), None)?,
+            )),
+        }
+    }
+
+    /// Returns a VM to the pool, or drops it if its seed was evicted while it was in use.
+    fn release(&self, vm: (Vec<u8>, RandomXVM)) {
+        let caches = self.caches.lock().unwrap();
+        if caches.iter().any(|(s, _)| *s == vm.0) {
+            self.vms.lock().unwrap().push(vm);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RandomXFlag, Verifier, TEST_VECTORS};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn verifier_test_vectors() {
+        let verifier = Verifier::new(RandomXFlag::get_recommended_flags(), 2);
+        for vector in TEST_VECTORS.iter() {
+            assert_eq!(
+                verifier.hash(vector.key, vector.input).unwrap(),
+                vector.hash
+            );
+            assert!(verifier.verify(vector.key, vector.input, &vector.hash));
+            assert!(!verifier.verify(vector.key, b"other input", &vector.hash));
+        }
+        assert!(!verifier.verify(&[], b"input", &[0u8; 32]));
+    }
+
+    #[test]
+    fn verifier_evicts_least_recently_used() {
+        let verifier = Verifier::new(RandomXFlag::default(), 1);
+        let a = &TEST_VECTORS[0];
+        let b = &TEST_VECTORS[3];
+        assert!(verifier.verify(a.key, a.input, &a.hash));
+        assert!(verifier.verify(b.key, b.input, &b.hash));
+        assert_eq!(verifier.caches.lock().unwrap().len(), 1);
+        assert!(verifier.verify(a.key, a.input, &a.hash));
+        assert_eq!(verifier.caches.lock().unwrap()[0].0, a.key);
+    }
+
+    #[test]
+    fn verifier_releases_evicted_caches() {
+        let verifier = Verifier::new(RandomXFlag::default(), 2);
+        // two idle VMs for the first seed, so one is left set to it when the next seed is hashed
+        let cache = verifier.cache(b"seed 0").unwrap();
+        let first = verifier.vm(b"seed 0", &cache).unwrap();
+        let second = verifier.vm(b"seed 0", &cache).unwrap();
+        verifier.release(first);
+        verifier.release(second);
+        drop(cache);
+        let mut caches = Vec::new();
+        for i in 0..5 {
+            let seed = format!("seed {}", i);
+            verifier.hash(seed.as_bytes(), b"input").unwrap();
+            let newest = verifier
+                .caches
+                .lock()
+                .unwrap()
+                .last()
+                .unwrap()
+                .1
+                .inner
+                .clone();
+            caches.push(Arc::downgrade(&newest));
+            let live = caches.iter().filter(|c| c.strong_count() > 0).count();
+            assert_eq!(live, (i + 1).min(2));
+        }
+        let vms = verifier.vms.lock().unwrap();
+        assert!(vms.iter().all(|(s, _)| s.as_slice() == b"seed 4"));
+    }
+
+    #[test]
+    fn verifier_is_shared_between_threads() {
+        let verifier = Verifier::new(RandomXFlag::get_recommended_flags(), 2);
+        thread::scope(|scope| {
+            for vector in TEST_VECTORS.iter() {
+                let verifier = &verifier;
+                scope.spawn(move || {
+                    assert!(verifier.verify(vector.key, vector.input, &vector.hash))
+                });
+            }
+        });
+        assert!(verifier.vms.lock().unwrap().len() <= TEST_VECTORS.len());
+    }
+}
@@ -0,0 +1,60 @@
+/* Uses the C API of the randomx-rs shared library; run by tests/capi.rs. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "randomx_rs.h"
+
+#define CHECK(cond)                                                                   \
+    do {                                                                              \
+        if (!(cond)) {                                                                \
+            fprintf(stderr, "%s:%d: %s failed: %s\n", __FILE__, __LINE__, #cond,       \
+                    randomx_rs_error_message(randomx_rs_last_error()));               \
+            return 1;                                                                 \
+        }                                                                             \
+    } while (0)
+
+/* First test vector of the RandomX reference implementation */
+static const char KEY[] = "test key 000";
+static const char INPUT[] = "This is a test";
+static const uint8_t HASH[RANDOMX_RS_HASH_SIZE] = {
+    0x63, 0x91, 0x83, 0xaa, 0xe1, 0xbf, 0x4c, 0x9a, 0x35, 0x88, 0x4c, 0xb4, 0x6b, 0x09, 0xca, 0xd9,
+    0x17, 0x5f, 0x04, 0xef, 0xd7, 0x68, 0x4e, 0x72, 0x62, 0xa0, 0xac, 0x1c, 0x2f, 0x0b, 0x4e, 0x3f,
+};
+
+int main(void) {
+    CHECK(randomx_rs_api_version() == RANDOMX_RS_API_VERSION);
+    uint32_t flags = randomx_rs_recommended_flags();
+
+    randomx_rs_cache *cache =
+        randomx_rs_cache_new("rx/0", flags, (const uint8_t *)KEY, strlen(KEY));
+    CHECK(cache != NULL);
+    randomx_rs_vm *vm = randomx_rs_vm_new(flags, cache, NULL);
+    CHECK(vm != NULL);
+    /* the VM keeps the cache alive */
+    randomx_rs_cache_free(cache);
+
+    uint8_t hash[RANDOMX_RS_HASH_SIZE];
+    CHECK(randomx_rs_vm_hash(vm, (const uint8_t *)INPUT, strlen(INPUT), hash) ==
+          RANDOMX_RS_ERROR_OK);
+    CHECK(memcmp(hash, HASH, sizeof(hash)) == 0);
+    CHECK(randomx_rs_vm_verify(vm, (const uint8_t *)INPUT, strlen(INPUT), HASH));
+    CHECK(!randomx_rs_vm_verify(vm, (const uint8_t *)KEY, strlen(KEY), HASH));
+    CHECK(randomx_rs_last_error() == RANDOMX_RS_ERROR_OK);
+    CHECK(randomx_rs_vm_hash(vm, NULL, 0, hash) == RANDOMX_RS_ERROR_EMPTY_INPUT);
+    randomx_rs_vm_free(vm);
+
+    CHECK(randomx_rs_vm_new(flags | RANDOMX_RS_FLAG_FULL_MEM, NULL, NULL) == NULL);
+    CHECK(randomx_rs_last_error() == RANDOMX_RS_ERROR_INVALID_FLAGS);
+    CHECK(randomx_rs_cache_new("cn/r", flags, (const uint8_t *)KEY, strlen(KEY)) == NULL);
+    CHECK(randomx_rs_last_error() == RANDOMX_RS_ERROR_UNSUPPORTED_ALGORITHM);
+
+    randomx_rs_verifier *verifier = randomx_rs_verifier_new(NULL, flags, 2);
+    CHECK(verifier != NULL);
+    CHECK(randomx_rs_verifier_verify(verifier, (const uint8_t *)KEY, strlen(KEY),
+                                     (const uint8_t *)INPUT, strlen(INPUT), HASH));
+    randomx_rs_verifier_free(verifier);
+
+    puts("C API ok");
+    return 0;
+}
@@ -1,20 +1,28 @@
 mod utils;
 
 use std::io::BufRead;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
+use utils::bench;
 use utils::client::Client;
-use utils::config::Config;
+use utils::config::{self, Config};
+use utils::dataset::DatasetParams;
+use utils::numa::Topology;
+use utils::selftest;
+use utils::verify::ShareVerifier;
 use utils::worker::SubmitWorker;
 use utils::worker::Worker;
+use utils::worker::{canonical_algorithm, job_algorithm};
 
 use cn_stratum::client::PoolClient;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 
 use log::*;
+use randomx_rs::Algorithm;
 
 const AGENT: &str = "pow#er/0.2.0";
 
@@ -39,6 +47,25 @@ fn main() {
                 .required(true)
                 .takes_value(true),
         )
+        .subcommand(
+            clap::SubCommand::with_name("bench")
+                .about("Hashes a fixed job offline and reports the hashrate")
+                .arg(
+                    clap::Arg::with_name("nonces")
+                        .long("nonces")
+                        .value_name("COUNT")
+                        .help("Number of nonces to hash, e.g. 1000, 250K or 1M")
+                        .default_value("1M")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("expect")
+                        .long("expect")
+                        .value_name("HASH")
+                        .help("Exits with an error if the final hash is not HASH")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
     let cfg: Config = std::fs::read_to_string(args.value_of("config").unwrap())
@@ -47,55 +74,178 @@ fn main() {
         .unwrap();
     debug!("config: {:?}", &cfg);
 
-    let mut rx_flags = randomx_rs::RandomXFlag::FLAG_DEFAULT;
-
-    if cfg.randomx.hard_aes {
-        rx_flags = rx_flags | randomx_rs::RandomXFlag::FLAG_HARD_AES;
+    let detected = randomx_rs::RandomXFlag::get_recommended_flags();
+    let rx_flags = cfg.randomx.flags(config::auto_flags(detected));
+    println!("capabilities:");
+    println!("\tdetected: {:?}", detected);
+    for (name, setting, flag) in cfg.randomx.flag_settings().iter() {
+        println!("\t{}: {:?} -> {}", name, setting, rx_flags.contains(*flag));
     }
+    let available: Vec<_> = Algorithm::available()
+        .iter()
+        .map(|algorithm| algorithm.name())
+        .collect();
+    println!("\talgorithms: {}", available.join(", "));
+    let algorithm = cfg.randomx.algo.as_ref().map(|name| {
+        match Algorithm::from_name(name).filter(|algorithm| algorithm.is_available()) {
+            Some(algorithm) => algorithm,
+            None => {
+                eprintln!("algo {} is not one of {}", name, available.join(", "));
+                std::process::exit(1);
+            }
+        }
+    });
+    // test every algorithm the workers may be asked to mine
+    let tested = match algorithm {
+        Some(algorithm) => vec![algorithm],
+        None => Algorithm::available().to_vec(),
+    };
+    let rx_flags = match selftest::select_flags(rx_flags, &tested) {
+        Some(flags) if flags == rx_flags => {
+            println!("\tself-test: passed");
+            flags
+        }
+        Some(flags) => {
+            println!("\tself-test: failed, falling back to {:?}", flags);
+            flags
+        }
+        None => {
+            eprintln!("self-test failed with every flag set, refusing to mine");
+            std::process::exit(1);
+        }
+    };
+    println!("\teffective: {:?}", rx_flags);
 
-    if cfg.randomx.jit {
-        rx_flags = rx_flags | randomx_rs::RandomXFlag::FLAG_JIT;
-    }
+    let worker_count = cfg.randomx.cores.len();
 
-    if cfg.randomx.argon2_avx2 {
-        rx_flags = rx_flags | randomx_rs::RandomXFlag::FLAG_ARGON2_AVX2;
+    let dataset_dir = cfg.randomx.dataset_dir.as_ref().map(PathBuf::from);
+    if let Some(dir) = &dataset_dir {
+        std::fs::create_dir_all(dir).expect("failed to create dataset_dir");
     }
+    let core_ids = core_affinity::get_core_ids().unwrap();
+    let topology = Topology::detect();
+    println!("\tnuma nodes: {}", topology.node_count());
+    let worker_cpus: Vec<_> = cfg
+        .randomx
+        .cores
+        .iter()
+        .map(|&w| core_ids[w as usize].id)
+        .collect();
+    let worker_nodes: Vec<_> = cfg
+        .randomx
+        .cores
+        .iter()
+        .map(|&w| topology.node_of(core_ids[w as usize].id))
+        .collect();
+    // one set of dataset params per worker, for the dataset of its node
+    let worker_params: Vec<_> = worker_nodes
+        .iter()
+        .map(|&node| {
+            // with a single node, initialize on as many unpinned threads as there are workers
+            let (cpus, spare_cpu) = if topology.node_count() > 1 {
+                let cpus = worker_cpus
+                    .iter()
+                    .zip(&worker_nodes)
+                    .filter(|&(_, &n)| n == node)
+                    .map(|(&cpu, _)| cpu)
+                    .collect();
+                (cpus, topology.spare_cpu(node, &worker_cpus))
+            } else {
+                (Vec::new(), None)
+            };
+            DatasetParams {
+                flags: rx_flags,
+                init_threads: if cpus.is_empty() {
+                    worker_count
+                } else {
+                    cpus.len()
+                },
+                node,
+                cpus,
+                spare_cpu,
+                dir: dataset_dir.clone(),
+            }
+        })
+        .collect();
 
-    if cfg.randomx.full_mem {
-        rx_flags = rx_flags | randomx_rs::RandomXFlag::FLAG_FULL_MEM;
+    if let Some(args) = args.subcommand_matches("bench") {
+        let nonces = args.value_of("nonces").unwrap();
+        let nonces = bench::parse_count(nonces).expect("invalid nonce count");
+        let hash = bench::run(&cfg.randomx.cores, rx_flags, &worker_params, nonces);
+        if let Some(expect) = args.value_of("expect") {
+            if hex::encode(hash) != expect.to_lowercase() {
+                eprintln!("final hash mismatch, expected {}", expect);
+                std::process::exit(1);
+            }
+            println!("final hash matches");
+        }
+        return;
     }
 
-    if cfg.randomx.large_pages {
-        rx_flags = rx_flags | randomx_rs::RandomXFlag::FLAG_LARGE_PAGES;
+    let mut pools = cfg.pools().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if pools.is_empty() {
+        eprintln!("no pool configured, add a [[pools]] entry");
+        std::process::exit(1);
     }
-
-    if cfg.randomx.argon2_ssse3 {
-        rx_flags = rx_flags | randomx_rs::RandomXFlag::FLAG_ARGON2_SSSE3;
+    // offer the pool only what the workers will mine
+    let advertised: Vec<String> = match algorithm {
+        Some(algorithm) => vec![algorithm.name().to_owned()],
+        None => available.iter().map(|&name| name.to_owned()).collect(),
+    };
+    for pool in &mut pools {
+        pool.algorithms = advertised.clone();
+        pool.resolve_algorithm = Some(canonical_algorithm);
     }
-
-    let client = PoolClient::connect(
-        &cfg.pool.address,
-        &cfg.pool.login,
-        &cfg.pool.pass,
-        cfg.pool.keepalive_s.map(Duration::from_secs),
-        AGENT,
-        Client::new,
-    )
-    .unwrap();
+    let client = PoolClient::connect_pools(pools, AGENT, Client::new)
+        .unwrap()
+        .failback(Duration::from_secs(cfg.failback_s));
     let work = client.handler().work();
+    let active_pool = client.handler().pool();
+    if cfg.randomx.self_test_full && rx_flags.contains(randomx_rs::RandomXFlag::FLAG_FULL_MEM) {
+        let (_, job) = work.current();
+        let first_algorithm = match job_algorithm(algorithm, &job) {
+            Some(algorithm) => algorithm,
+            None => {
+                eprintln!("first job is for an unsupported algorithm");
+                std::process::exit(1);
+            }
+        };
+        let dataset = work.dataset(&worker_params[0], first_algorithm, &job.seed_hash());
+        if dataset.is_light() {
+            println!("\tfull-mode self-test: skipped, no dataset allocated");
+        } else if let Err(e) = selftest::check_dataset(&dataset, rx_flags) {
+            eprintln!("full-mode self-test failed: {}", e);
+            std::process::exit(1);
+        } else {
+            println!("\tfull-mode self-test: passed");
+        }
+    }
     let pool = client.write_handle();
     thread::Builder::new()
         .name("poolclient".into())
-        .spawn(move || client.run())
+        .spawn(move || {
+            if let Err(e) = client.run() {
+                error!("gave up reconnecting to pool: {}", e);
+            }
+        })
         .unwrap();
 
-    let core_ids = core_affinity::get_core_ids().unwrap();
-    let worker_count = cfg.randomx.cores.len();
     let mut workerstats = Vec::with_capacity(cfg.randomx.cores.len());
 
     let (tx, rx): (Sender<_>, Receiver<_>) = mpsc::channel();
 
-    let submit_worker = SubmitWorker {};
+    let hw_errors = Arc::new(AtomicUsize::new(0));
+    let submit_worker = SubmitWorker {
+        verifier: if cfg.randomx.verify_shares {
+            Some(ShareVerifier::new(rx_flags))
+        } else {
+            None
+        },
+        hw_errors: Arc::clone(&hw_errors),
+    };
 
     thread::Builder::new()
         .name("sender".into())
@@ -104,17 +254,21 @@ fn main() {
         })
         .unwrap();
 
-    for (i, w) in cfg.randomx.cores.into_iter().enumerate() {
+    for (i, (w, params)) in cfg.randomx.cores.into_iter().zip(worker_params).enumerate() {
         let hash_count = Arc::new(AtomicUsize::new(0));
-        workerstats.push(Arc::clone(&hash_count));
+        let vm_flags = Arc::new(AtomicU32::new(0));
+        workerstats.push((Arc::clone(&hash_count), Arc::clone(&vm_flags)));
         let core = core_ids[w as usize];
         debug!("starting worker{} on core {:?}", i, w);
         let worker = Worker {
             hash_count,
+            vm_flags,
             work: Arc::clone(&work),
-            core,
             worker_id: i as u32,
             step: worker_count as u32,
+            dataset: params,
+            precompute: cfg.randomx.precompute,
+            algorithm,
         };
 
         let thread_tx = tx.clone();
@@ -130,7 +284,7 @@ fn main() {
 
     let mut prevstats: Vec<_> = workerstats
         .iter()
-        .map(|w| w.load(Ordering::Relaxed))
+        .map(|(w, _)| w.load(Ordering::Relaxed))
         .collect();
 
     let start = Instant::now();
@@ -144,11 +298,36 @@ fn main() {
         let cur_dur = now - prev_start;
         let total_dur = now - start;
         prev_start = now;
+        println!("\tpool: {}", active_pool.lock().unwrap());
+        for (node, ds) in work.current_datasets() {
+            match ds.dataset_flags() {
+                Some(flags) => println!(
+                    "\tnode {}: {}, cache: {:?}, dataset: {:?}",
+                    node,
+                    ds.algorithm(),
+                    ds.cache_flags(),
+                    flags
+                ),
+                None => println!(
+                    "\tnode {}: {}, cache: {:?}, dataset: light mode",
+                    node,
+                    ds.algorithm(),
+                    ds.cache_flags()
+                ),
+            }
+        }
         let mut cur_hashes = 0;
-        for (i, (prev, new)) in prevstats.iter_mut().zip(&workerstats).enumerate() {
+        for (i, (prev, (new, vm_flags))) in prevstats.iter_mut().zip(&workerstats).enumerate() {
             let new = new.load(Ordering::Relaxed);
             let cur = new - *prev;
-            println!("\t{}: {} H/s", i, (cur as f32) / dur_to_f32(&cur_dur));
+            let vm_flags =
+                randomx_rs::RandomXFlag::from_bits_truncate(vm_flags.load(Ordering::Relaxed));
+            println!(
+                "\t{}: {} H/s ({:?})",
+                i,
+                (cur as f32) / dur_to_f32(&cur_dur),
+                vm_flags
+            );
             cur_hashes += cur;
             *prev = new;
         }
@@ -161,6 +340,9 @@ fn main() {
             "\ttotal (all time): {} H/s",
             (total_hashes as f32) / dur_to_f32(&total_dur)
         );
+        if cfg.randomx.verify_shares {
+            println!("\thardware errors: {}", hw_errors.load(Ordering::Relaxed));
+        }
         await_input.next();
     }
 }
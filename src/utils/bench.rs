@@ -0,0 +1,136 @@
+//! Offline benchmark over a fixed job, without a pool connection.
+//!
+//! Every worker hashes its share of the nonces `0..nonces` over the same blob and seed. The final
+//! hash is the XOR of all the hashes, so it only depends on the number of nonces, not on the
+//! number of cores or the flags, and can be compared across machines and builds.
+
+use crate::utils::dataset::DatasetParams;
+use crate::utils::work::Work;
+use crate::utils::worker::Worker;
+use cn_stratum::client::Job;
+use randomx_rs::{Algorithm, RandomXFlag};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const BENCH_BLOB: &str = "0c0cbbd9dffa056ed9f488ea952afa3ff2663b1ec70a60baea5543c2bab0f25a9e830d2d40eb5f000000009caec86efcd1554b50015f58db69445c62381943a4385ce402ae15ded652657019";
+const BENCH_SEED: &str = "308c6f50a99d6854394ea0e471cbd5234a29554a86df1f6708a4cbe2093a4078";
+const BENCH_HEIGHT: u64 = 2182450;
+
+fn bench_job() -> Job {
+    let json = format!(
+        r#"{{"blob":"{}","job_id":"bench","target":"00000000","height":{},"seed_hash":"{}"}}"#,
+        BENCH_BLOB, BENCH_HEIGHT, BENCH_SEED
+    );
+    serde_json::from_str(&json).expect("invalid bench job")
+}
+
+/// Parse a nonce count such as `1000`, `250K` or `1M`.
+pub fn parse_count(s: &str) -> Option<u32> {
+    let (digits, scale) = match s.chars().last()? {
+        'k' | 'K' => (&s[..s.len() - 1], 1_000),
+        'm' | 'M' => (&s[..s.len() - 1], 1_000_000),
+        _ => (s, 1),
+    };
+    digits.parse::<u32>().ok()?.checked_mul(scale)
+}
+
+/// Hash `nonces` nonces on `cores`, with the dataset params in `params` for each, print the speed
+/// of each worker and in total, and return the final hash. Datasets are neither loaded nor saved.
+pub fn run(
+    cores: &[u32],
+    rx_flags: RandomXFlag,
+    params: &[DatasetParams],
+    nonces: u32,
+) -> [u8; 32] {
+    let work = Arc::new(Work::new(bench_job()));
+    let core_ids = core_affinity::get_core_ids().unwrap();
+    // without a dataset directory: the init is part of the benchmark, and `main` returns right
+    // after it, which would cut a background save short
+    let params: Vec<_> = params
+        .iter()
+        .map(|params| DatasetParams {
+            dir: None,
+            ..params.clone()
+        })
+        .collect();
+
+    let init = Instant::now();
+    for params in &params {
+        work.dataset(params, Algorithm::Rx0, BENCH_SEED);
+    }
+    println!("dataset ready in {:.1}s", init.elapsed().as_secs_f64());
+
+    let workers: Vec<_> = cores
+        .iter()
+        .zip(&params)
+        .enumerate()
+        .map(|(i, (&w, params))| {
+            let core = core_ids[w as usize];
+            let worker = Worker {
+                hash_count: Arc::new(AtomicUsize::new(0)),
+                vm_flags: Arc::new(AtomicU32::new(0)),
+                work: Arc::clone(&work),
+                worker_id: i as u32,
+                step: cores.len() as u32,
+                dataset: params.clone(),
+                precompute: false,
+                algorithm: None,
+            };
+            let hash_count = Arc::clone(&worker.hash_count);
+            let vm_flags = Arc::clone(&worker.vm_flags);
+            let handle = thread::Builder::new()
+                .name(format!("bench{}", i))
+                .spawn(move || {
+                    core_affinity::set_for_current(core);
+                    worker.bench(rx_flags, nonces)
+                })
+                .unwrap();
+            (handle, hash_count, vm_flags)
+        })
+        .collect();
+
+    let mut final_hash = [0u8; 32];
+    let mut slowest = 0f64;
+    for (i, (handle, hash_count, vm_flags)) in workers.into_iter().enumerate() {
+        let (hash, elapsed) = handle.join().expect("bench worker panicked");
+        let hashes = hash_count.load(Ordering::Relaxed);
+        let vm_flags = RandomXFlag::from_bits_truncate(vm_flags.load(Ordering::Relaxed));
+        println!(
+            "\t{}: {} H/s ({:?})",
+            i,
+            hashes as f64 / elapsed.as_secs_f64(),
+            vm_flags
+        );
+        slowest = slowest.max(elapsed.as_secs_f64());
+        for (x, y) in final_hash.iter_mut().zip(hash.iter()) {
+            *x ^= y;
+        }
+    }
+    println!("\ttotal: {} H/s", f64::from(nonces) / slowest);
+    println!(
+        "final hash for {} nonces: {}",
+        nonces,
+        hex::encode(final_hash)
+    );
+    final_hash
+}
+
+#[test]
+fn test_parse_count() {
+    assert_eq!(parse_count("1000"), Some(1000));
+    assert_eq!(parse_count("250K"), Some(250_000));
+    assert_eq!(parse_count("1M"), Some(1_000_000));
+    assert_eq!(parse_count("10m"), Some(10_000_000));
+    assert_eq!(parse_count("5000M"), None);
+    assert_eq!(parse_count("M"), None);
+    assert_eq!(parse_count(""), None);
+}
+
+#[test]
+fn test_bench_job() {
+    let job = bench_job();
+    assert_eq!(job.height(), BENCH_HEIGHT);
+    assert_eq!(job.seed_hash(), BENCH_SEED);
+}
@@ -1,21 +1,28 @@
 use crate::utils::work::Work;
-use cn_stratum::client::{ErrorReply, Job, JobAssignment, MessageHandler, RequestId};
+use cn_stratum::client::{Error, ErrorReply, Job, JobAssignment, MessageHandler, RequestId};
 use log::*;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 pub struct Client {
     work: Arc<Work>,
+    /// Address of the pool being mined on.
+    pool: Arc<Mutex<String>>,
 }
 
 impl Client {
     pub fn new(job: Job) -> Self {
         let work = Arc::new(Work::new(job));
-        Client { work }
+        let pool = Arc::new(Mutex::new(String::new()));
+        Client { work, pool }
     }
 
     pub fn work(&self) -> Arc<Work> {
         Arc::clone(&self.work)
     }
+
+    pub fn pool(&self) -> Arc<Mutex<String>> {
+        Arc::clone(&self.pool)
+    }
 }
 
 impl MessageHandler for Client {
@@ -42,4 +49,18 @@ impl MessageHandler for Client {
     fn job_reply(&mut self, _id: RequestId, _job: Box<JobAssignment>) {
         warn!("unexpected job reply...");
     }
+
+    fn logged_in(&mut self, address: &str) {
+        info!("mining on {}", address);
+        *self.pool.lock().unwrap() = address.to_owned();
+    }
+
+    fn disconnected(&mut self, error: &Error) {
+        warn!("lost connection to pool ({}), reconnecting", error);
+    }
+
+    fn reconnected(&mut self, j: Job) {
+        info!("reconnected to pool, new job: {:?}", j);
+        self.work.set_current(j);
+    }
 }
@@ -1,4 +1,9 @@
+use cn_stratum::client::{Pool, ReconnectPolicy, TlsOptions};
+use randomx_rs::RandomXFlag;
+use serde::de::{Deserializer, Error, Unexpected};
+use serde::ser::Serializer;
 use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -7,23 +12,339 @@ pub struct ClientConfig {
     pub login: String,
     pub pass: String,
     pub keepalive_s: Option<u64>,
+    /// Pools with a lower priority are preferred; pools with the same one are tried in the order
+    /// they are listed.
+    #[serde(default)]
+    pub priority: u32,
+    /// Failed connects before failing over to the next pool. A single pool is retried forever.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Seconds to wait before reconnecting, doubled after every failed attempt up to `retry_max_s`.
+    #[serde(default = "default_retry_s")]
+    pub retry_s: u64,
+    #[serde(default = "default_retry_max_s")]
+    pub retry_max_s: u64,
+    /// SHA-256 fingerprint of the certificate of a `stratum+ssl://` pool, in hex with or without
+    /// colons. Only that certificate is accepted.
+    #[serde(default)]
+    pub tls_fingerprint: Option<String>,
+    /// Accept any certificate from a `stratum+ssl://` pool, e.g. a self-signed proxy.
+    #[serde(default)]
+    pub tls_insecure: bool,
 }
 
+impl ClientConfig {
+    fn tls(&self) -> Result<TlsOptions, String> {
+        let fingerprint = match &self.tls_fingerprint {
+            Some(hex) => {
+                let bytes = hex::decode(hex.replace(':', ""))
+                    .ok()
+                    .filter(|bytes| bytes.len() == 32)
+                    .ok_or_else(|| format!("invalid tls_fingerprint for {}", self.address))?;
+                let mut fingerprint = [0; 32];
+                fingerprint.copy_from_slice(&bytes);
+                Some(fingerprint)
+            }
+            None => None,
+        };
+        Ok(TlsOptions {
+            fingerprint,
+            insecure: self.tls_insecure,
+        })
+    }
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_retry_s() -> u64 {
+    1
+}
+
+fn default_retry_max_s() -> u64 {
+    60
+}
+
+fn default_failback_s() -> u64 {
+    60
+}
+
+/// Setting of a RandomX flag: `true`, `false` or `"auto"`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlagSetting {
+    On,
+    Off,
+    #[default]
+    Auto,
+}
+
+impl FlagSetting {
+    /// Whether the flag is set, given whether it is set when `"auto"`.
+    pub fn resolve(self, auto: bool) -> bool {
+        match self {
+            FlagSetting::On => true,
+            FlagSetting::Off => false,
+            FlagSetting::Auto => auto,
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FlagSetting {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Str(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(true) => Ok(FlagSetting::On),
+            Raw::Bool(false) => Ok(FlagSetting::Off),
+            Raw::Str(ref s) if s == "auto" => Ok(FlagSetting::Auto),
+            Raw::Str(s) => Err(D::Error::invalid_value(
+                Unexpected::Str(&s),
+                &"true, false or \"auto\"",
+            )),
+        }
+    }
+}
+
+impl serde::Serialize for FlagSetting {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FlagSetting::On => serializer.serialize_bool(true),
+            FlagSetting::Off => serializer.serialize_bool(false),
+            FlagSetting::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+/// Each flag is `true`, `false` or `"auto"`, which is also the default when it is left out.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct RandomXConfig {
     pub cores: Vec<u32>,
-    pub hard_aes: bool,
-    pub jit: bool,
-    pub argon2_avx2: bool,
-    pub full_mem: bool,
-    pub large_pages: bool,
-    pub argon2_ssse3: bool,
+    #[serde(default)]
+    pub hard_aes: FlagSetting,
+    #[serde(default)]
+    pub jit: FlagSetting,
+    /// Keep JIT pages from being writable and executable at the same time.
+    #[serde(default)]
+    pub secure: FlagSetting,
+    #[serde(default)]
+    pub argon2_avx2: FlagSetting,
+    #[serde(default)]
+    pub full_mem: FlagSetting,
+    #[serde(default)]
+    pub large_pages: FlagSetting,
+    #[serde(default)]
+    pub argon2_ssse3: FlagSetting,
+    /// Build the next epoch's dataset in the background when the pool announces its seed.
+    #[serde(default)]
+    pub precompute: bool,
+    /// Directory to save initialized datasets in, to load them on restart instead of rebuilding.
+    #[serde(default)]
+    pub dataset_dir: Option<String>,
+    /// Hash every share again in a light-mode VM before submitting it.
+    #[serde(default)]
+    pub verify_shares: bool,
+    /// Also self-test full mode on the first dataset before mining; the startup self-test only
+    /// covers light mode, as it would otherwise need a dataset per algorithm.
+    #[serde(default)]
+    pub self_test_full: bool,
+    /// Algorithm to mine every job with (e.g. `"rx/wow"`), instead of the one the pool names in
+    /// the job, or rx/0 if it names none.
+    #[serde(default)]
+    pub algo: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    pub pool: ClientConfig,
+    /// Seconds between attempts to move back to a more preferred pool while mining on another.
+    #[serde(default = "default_failback_s")]
+    pub failback_s: u64,
+    /// A single pool, the same as a `[[pools]]` entry.
+    #[serde(default)]
+    pub pool: Option<ClientConfig>,
+    /// Pools to fail over between.
+    #[serde(default)]
+    pub pools: Vec<ClientConfig>,
     pub randomx: RandomXConfig,
 }
+
+impl Config {
+    /// The configured pools, most preferred first.
+    pub fn pools(&self) -> Result<Vec<Pool>, String> {
+        let mut configs: Vec<_> = self.pool.iter().chain(&self.pools).collect();
+        configs.sort_by_key(|cfg| cfg.priority);
+        let failover = configs.len() > 1;
+        configs
+            .into_iter()
+            .map(|cfg| {
+                Ok(Pool {
+                    policy: ReconnectPolicy {
+                        initial: Duration::from_secs(cfg.retry_s),
+                        max: Duration::from_secs(cfg.retry_max_s),
+                        max_attempts: if failover { Some(cfg.retries) } else { None },
+                        ..Default::default()
+                    },
+                    tls: cfg.tls()?,
+                    ..Pool::new(
+                        &cfg.address,
+                        &cfg.login,
+                        &cfg.pass,
+                        cfg.keepalive_s.map(Duration::from_secs),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl RandomXConfig {
+    /// The flag settings by name, with the flag each one controls.
+    pub fn flag_settings(&self) -> [(&'static str, FlagSetting, RandomXFlag); 7] {
+        [
+            ("hard_aes", self.hard_aes, RandomXFlag::FLAG_HARD_AES),
+            ("jit", self.jit, RandomXFlag::FLAG_JIT),
+            ("secure", self.secure, RandomXFlag::FLAG_SECURE),
+            (
+                "argon2_avx2",
+                self.argon2_avx2,
+                RandomXFlag::FLAG_ARGON2_AVX2,
+            ),
+            (
+                "argon2_ssse3",
+                self.argon2_ssse3,
+                RandomXFlag::FLAG_ARGON2_SSSE3,
+            ),
+            ("full_mem", self.full_mem, RandomXFlag::FLAG_FULL_MEM),
+            (
+                "large_pages",
+                self.large_pages,
+                RandomXFlag::FLAG_LARGE_PAGES,
+            ),
+        ]
+    }
+
+    /// The flags to request, with the `"auto"` settings taken from `auto`.
+    pub fn flags(&self, auto: RandomXFlag) -> RandomXFlag {
+        self.flag_settings()
+            .iter()
+            .filter(|(_, setting, flag)| setting.resolve(auto.contains(*flag)))
+            .fold(RandomXFlag::FLAG_DEFAULT, |flags, (_, _, flag)| {
+                flags | *flag
+            })
+    }
+}
+
+/// The flags `"auto"` settings resolve to: the CPU features in `detected`, plus full_mem and
+/// large_pages (they fall back when they cannot be allocated), plus secure on systems that do not
+/// allow memory to be writable and executable at the same time.
+pub fn auto_flags(detected: RandomXFlag) -> RandomXFlag {
+    let mut flags = detected | RandomXFlag::FLAG_FULL_MEM | RandomXFlag::FLAG_LARGE_PAGES;
+    if cfg!(any(target_os = "macos", target_os = "openbsd")) {
+        flags |= RandomXFlag::FLAG_SECURE;
+    }
+    flags
+}
+
+#[test]
+fn test_flag_settings() {
+    let cfg: RandomXConfig = toml::from_str(
+        r#"
+        cores = [0]
+        hard_aes = "auto"
+        jit = true
+        argon2_avx2 = false
+        argon2_ssse3 = "auto"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.hard_aes, FlagSetting::Auto);
+    assert_eq!(cfg.jit, FlagSetting::On);
+    assert_eq!(cfg.argon2_avx2, FlagSetting::Off);
+    assert_eq!(cfg.secure, FlagSetting::Auto);
+
+    let detected = RandomXFlag::FLAG_HARD_AES | RandomXFlag::FLAG_ARGON2_AVX2;
+    let flags = cfg.flags(auto_flags(detected));
+    assert!(flags.contains(
+        RandomXFlag::FLAG_HARD_AES
+            | RandomXFlag::FLAG_JIT
+            | RandomXFlag::FLAG_FULL_MEM
+            | RandomXFlag::FLAG_LARGE_PAGES
+    ));
+    assert!(!flags.contains(RandomXFlag::FLAG_ARGON2_AVX2));
+    assert!(!flags.contains(RandomXFlag::FLAG_ARGON2_SSSE3));
+
+    assert!(toml::from_str::<RandomXConfig>("cores = [0]\njit = \"yes\"").is_err());
+}
+
+#[test]
+fn test_pools() {
+    let cfg: Config = toml::from_str(
+        r#"
+        [[pools]]
+        address = "backup:3333"
+        login = "a"
+        pass = "x"
+        priority = 1
+
+        [[pools]]
+        address = "primary:3333"
+        login = "b"
+        pass = "x"
+        keepalive_s = 60
+        retries = 5
+
+        [randomx]
+        cores = [0]
+        "#,
+    )
+    .unwrap();
+    let pools = cfg.pools().unwrap();
+    assert_eq!(pools[0].address, "primary:3333");
+    assert_eq!(pools[0].keepalive, Some(Duration::from_secs(60)));
+    assert_eq!(pools[0].policy.max_attempts, Some(5));
+    assert_eq!(pools[1].address, "backup:3333");
+    assert_eq!(pools[1].policy.max_attempts, Some(3));
+
+    let cfg: Config = toml::from_str(
+        r#"
+        [pool]
+        address = "primary:3333"
+        login = "b"
+        pass = "x"
+
+        [randomx]
+        cores = [0]
+        "#,
+    )
+    .unwrap();
+    let pools = cfg.pools().unwrap();
+    assert_eq!(pools.len(), 1);
+    assert_eq!(pools[0].policy.max_attempts, None);
+}
+
+#[test]
+fn test_tls_fingerprint() {
+    let pool = |fingerprint: &str| -> ClientConfig {
+        toml::from_str(&format!(
+            r#"
+            address = "stratum+ssl://pool:443"
+            login = "a"
+            pass = "x"
+            tls_fingerprint = "{}"
+            "#,
+            fingerprint
+        ))
+        .unwrap()
+    };
+    let hex = "ab".repeat(32);
+    let colons = vec!["AB"; 32].join(":");
+    assert_eq!(pool(&hex).tls().unwrap().fingerprint, Some([0xab; 32]));
+    assert_eq!(pool(&colons).tls().unwrap().fingerprint, Some([0xab; 32]));
+    assert!(pool("abcd").tls().is_err());
+}
@@ -0,0 +1,400 @@
+use crate::utils::unhexlify;
+use core_affinity::CoreId;
+use log::*;
+use randomx_rs::{Algorithm, RandomXCache, RandomXDataset, RandomXError, RandomXFlag, RandomXVM};
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Number of saved datasets to keep in the dataset directory: those of the current and the next
+/// seed.
+const KEEP_SAVED: usize = 2;
+
+/// Number of slices a precomputed dataset is initialized in, so that cancelling it takes effect
+/// within a few seconds.
+const PRECOMPUTE_SLICES: usize = 64;
+
+/// How datasets are built.
+#[derive(Clone, Debug)]
+pub struct DatasetParams {
+    pub flags: RandomXFlag,
+    /// Number of threads to initialize a dataset on.
+    pub init_threads: usize,
+    /// NUMA node the dataset is for.
+    pub node: usize,
+    /// CPUs to pin the initializing threads to, so the dataset is placed on their node. Empty
+    /// to leave them unpinned.
+    pub cpus: Vec<usize>,
+    /// CPU of the node that no worker is pinned to, for building the next epoch's dataset in the
+    /// background. `None` to leave that thread unpinned.
+    pub spare_cpu: Option<usize>,
+    /// Directory to keep initialized datasets in across restarts, one file per algorithm and seed.
+    pub dir: Option<PathBuf>,
+}
+
+/// Cache and dataset for one algorithm and seed hash, shared by every worker thread.
+pub struct Dataset {
+    algorithm: Algorithm,
+    seed: String,
+    cache: RandomXCache,
+    cache_flags: RandomXFlag,
+    /// The dataset and the flags it was allocated with, `None` in light mode.
+    dataset: Option<(RandomXDataset, RandomXFlag)>,
+    /// Where to save the dataset, until it is saved; `None` if it was loaded or is not kept.
+    unsaved: Mutex<Option<PathBuf>>,
+}
+
+impl Dataset {
+    /// Build the cache and dataset of `algorithm` for `seed`. The dataset is loaded from
+    /// `params.dir` if it was saved there before, and initialized otherwise; see `save`.
+    ///
+    /// If allocating with large pages fails, the allocation is retried without them; if the
+    /// dataset still cannot be allocated, only the cache is kept and VMs run in light mode.
+    pub fn new(params: &DatasetParams, algorithm: Algorithm, seed: &str) -> Self {
+        Dataset::create(params, algorithm, seed, true, None)
+            .unwrap_or_else(|e| panic!("failed to create dataset for seed {}: {}", seed, e))
+    }
+
+    /// Build as in `new`, but on the calling thread only, and only with the flags of `params`:
+    /// fail rather than fall back to normal pages or light mode. Initialization stops with an
+    /// error within one of `PRECOMPUTE_SLICES` slices once `cancel` is set.
+    pub fn precompute(
+        params: &DatasetParams,
+        algorithm: Algorithm,
+        seed: &str,
+        cancel: &AtomicBool,
+    ) -> Result<Self, RandomXError> {
+        Dataset::create(params, algorithm, seed, false, Some(cancel))
+    }
+
+    fn create(
+        params: &DatasetParams,
+        algorithm: Algorithm,
+        seed: &str,
+        fallback: bool,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<Self, RandomXError> {
+        let key = unhexlify(seed).expect("unhexlify error");
+        let (cache, cache_flags) = allocate(params.flags, fallback, |flags| {
+            RandomXCache::with_algorithm(algorithm, flags, &key)
+        })?;
+        info!(
+            "{} cache for seed {} created with flags {:?}",
+            algorithm, seed, cache_flags
+        );
+        let path = params
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(file_name(algorithm, seed)));
+        let mut unsaved = None;
+        let dataset = if params.flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+            match allocate(params.flags, fallback, |flags| {
+                Dataset::build(
+                    params,
+                    flags,
+                    algorithm,
+                    path.as_deref(),
+                    &cache,
+                    &key,
+                    cancel,
+                )
+            }) {
+                Ok(((dataset, loaded), flags)) => {
+                    info!("dataset for seed {} created with flags {:?}", seed, flags);
+                    if !loaded {
+                        unsaved = path;
+                    }
+                    Some((dataset, flags))
+                }
+                Err(ref e) if fallback && e.is_allocation() => {
+                    warn!("{}, falling back to light mode", e);
+                    None
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        Ok(Dataset {
+            algorithm,
+            seed: seed.to_owned(),
+            cache,
+            cache_flags,
+            dataset,
+            unsaved: Mutex::new(unsaved),
+        })
+    }
+
+    /// Load the dataset from `path`, or initialize it. Returns it with whether it was loaded.
+    fn build(
+        params: &DatasetParams,
+        flags: RandomXFlag,
+        algorithm: Algorithm,
+        path: Option<&Path>,
+        cache: &RandomXCache,
+        key: &[u8],
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(RandomXDataset, bool), RandomXError> {
+        if let Some(path) = path {
+            if let Some(dataset) = Dataset::load(algorithm, flags, path, key)? {
+                return Ok((dataset, true));
+            }
+        }
+        Ok((Dataset::init(params, flags, cache, cancel)?, false))
+    }
+
+    /// Save the dataset to the dataset directory if it was initialized rather than loaded and
+    /// has not been saved yet, and then delete all but the most recently saved datasets there.
+    /// This writes about 2 GiB, so call it once the dataset is in use, off the workers' path.
+    pub fn save(&self) {
+        let path = match self.unsaved.lock().unwrap().take() {
+            Some(path) => path,
+            None => return,
+        };
+        let dataset = match &self.dataset {
+            Some((dataset, _)) => dataset,
+            None => return,
+        };
+        let key = unhexlify(&self.seed).expect("unhexlify error");
+        if let Err(e) = dataset.save(&path, &key) {
+            warn!("failed to save dataset to {}: {}", path.display(), e);
+            return;
+        }
+        info!("dataset saved to {}", path.display());
+        if let Some(dir) = path.parent() {
+            if let Err(e) = prune(dir) {
+                warn!("failed to clean up {}: {}", dir.display(), e);
+            }
+        }
+    }
+
+    /// Load a saved dataset; a stale or corrupt file is reported and then rebuilt by the caller.
+    /// Only a failure to allocate the dataset is returned as an error.
+    fn load(
+        algorithm: Algorithm,
+        flags: RandomXFlag,
+        path: &Path,
+        key: &[u8],
+    ) -> Result<Option<RandomXDataset>, RandomXError> {
+        match RandomXDataset::load_with_algorithm(algorithm, flags, path, key) {
+            Ok(dataset) => {
+                info!("dataset loaded from {}", path.display());
+                Ok(Some(dataset))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                if let Some(e) = e.get_ref().and_then(|e| e.downcast_ref::<RandomXError>()) {
+                    if e.is_allocation() {
+                        return Err(e.clone());
+                    }
+                }
+                warn!("rebuilding dataset {}: {}", path.display(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn init(
+        params: &DatasetParams,
+        flags: RandomXFlag,
+        cache: &RandomXCache,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<RandomXDataset, RandomXError> {
+        let reported = AtomicU64::new(0);
+        let progress = |done, total| {
+            let decile = done * 10 / total;
+            if reported.fetch_max(decile, Ordering::Relaxed) < decile {
+                info!("dataset init: {}%", decile * 10);
+            }
+        };
+        if let Some(cancel) = cancel {
+            // one slice after another, checking for a cancel in between; a cancelled build
+            // leaves the dataset incomplete, which fails
+            let slices = PRECOMPUTE_SLICES;
+            return RandomXDataset::new_with(flags, cache, 0, slices, &progress, |slices| {
+                for slice in slices {
+                    if cancel.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    slice.init();
+                }
+            });
+        }
+        if params.cpus.is_empty() {
+            return RandomXDataset::new_parallel(flags, cache, 0, params.init_threads, progress);
+        }
+        RandomXDataset::new_with(flags, cache, 0, params.init_threads, &progress, |slices| {
+            thread::scope(|scope| {
+                for (i, slice) in slices.into_iter().enumerate() {
+                    let core = CoreId {
+                        id: params.cpus[i % params.cpus.len()],
+                    };
+                    thread::Builder::new()
+                        .name(format!("rx-dataset-{}", i))
+                        .spawn_scoped(scope, move || {
+                            core_affinity::set_for_current(core);
+                            slice.init()
+                        })
+                        .unwrap();
+                }
+            })
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn seed(&self) -> &str {
+        &self.seed
+    }
+
+    /// Whether this is the dataset of `algorithm` for `seed`.
+    pub fn is_for(&self, algorithm: Algorithm, seed: &str) -> bool {
+        self.algorithm == algorithm && self.seed == seed
+    }
+
+    /// Flags the cache was allocated with.
+    pub fn cache_flags(&self) -> RandomXFlag {
+        self.cache_flags
+    }
+
+    /// Flags the dataset was allocated with, `None` in light mode.
+    pub fn dataset_flags(&self) -> Option<RandomXFlag> {
+        self.dataset.as_ref().map(|(_, flags)| *flags)
+    }
+
+    /// Whether VMs hash over the cache only, because no dataset was requested or allocated.
+    pub fn is_light(&self) -> bool {
+        self.dataset.is_none()
+    }
+
+    /// Create a VM over this dataset, or over the cache in light mode. Returns the VM with the
+    /// flags that took effect.
+    pub fn vm(&self, flags: RandomXFlag) -> (RandomXVM, RandomXFlag) {
+        let flags = if self.is_light() {
+            flags - RandomXFlag::FLAG_FULL_MEM
+        } else {
+            flags
+        };
+        let dataset = self.dataset.as_ref().map(|(dataset, _)| dataset);
+        with_fallback(flags, |flags| {
+            RandomXVM::new(flags, Some(&self.cache), dataset)
+        })
+        .unwrap_or_else(|e| panic!("failed to create vm: {}", e))
+    }
+
+    /// Point an existing VM at this dataset, or at its cache for a light-mode VM.
+    pub fn attach(&self, vm: &mut RandomXVM, flags: RandomXFlag) {
+        match &self.dataset {
+            Some((dataset, _)) if flags.contains(RandomXFlag::FLAG_FULL_MEM) => vm
+                .reinit_dataset(dataset)
+                .expect("error reinitializing dataset"),
+            _ => vm
+                .reinit_cache(&self.cache)
+                .expect("error reinitializing cache"),
+        }
+    }
+}
+
+/// Name of the saved dataset of `algorithm` for `seed`; rx/0 keeps the plain `{seed}.rxds`.
+fn file_name(algorithm: Algorithm, seed: &str) -> String {
+    match algorithm {
+        Algorithm::Rx0 => format!("{}.rxds", seed),
+        _ => format!("{}-{}.rxds", seed, algorithm.name().replace('/', "-")),
+    }
+}
+
+/// Delete all but the `KEEP_SAVED` most recently saved datasets in `dir`, and the temporary files
+/// of saves that other processes did not finish.
+fn prune(dir: &Path) -> io::Result<()> {
+    let mut saved = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.ends_with(".rxds") {
+            saved.push((entry.metadata()?.modified()?, entry.path()));
+        } else if is_stale_tmp(name, process::id()) {
+            info!("removing unfinished save {}", entry.path().display());
+            fs::remove_file(entry.path())?;
+        }
+    }
+    saved.sort_by_key(|(modified, _)| Reverse(*modified));
+    for (_, path) in saved.into_iter().skip(KEEP_SAVED) {
+        info!("removing old dataset {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Whether `name` is the temporary file of a save by a process other than `pid`: saves write
+/// `{name}.{pid}-{n}.tmp` and rename it when done.
+fn is_stale_tmp(name: &str, pid: u32) -> bool {
+    let tag = match name
+        .strip_suffix(".tmp")
+        .and_then(|name| name.rsplit_once('.'))
+    {
+        Some((_, tag)) => tag,
+        None => return false,
+    };
+    match tag.split_once('-').map(|(owner, _)| owner.parse::<u32>()) {
+        Some(Ok(owner)) => owner != pid,
+        _ => false,
+    }
+}
+
+/// Run `alloc` with `flags`, falling back as in `with_fallback` if `fallback` is set.
+fn allocate<T, F>(
+    flags: RandomXFlag,
+    fallback: bool,
+    mut alloc: F,
+) -> Result<(T, RandomXFlag), RandomXError>
+where
+    F: FnMut(RandomXFlag) -> Result<T, RandomXError>,
+{
+    if fallback {
+        with_fallback(flags, alloc)
+    } else {
+        alloc(flags).map(|x| (x, flags))
+    }
+}
+
+/// Run `alloc` with `flags`, and again without FLAG_LARGE_PAGES if large pages could not be
+/// allocated. Returns the result with the flags that took effect.
+fn with_fallback<T, F>(flags: RandomXFlag, mut alloc: F) -> Result<(T, RandomXFlag), RandomXError>
+where
+    F: FnMut(RandomXFlag) -> Result<T, RandomXError>,
+{
+    match alloc(flags) {
+        Err(e @ RandomXError::LargePagesFailed { .. }) => {
+            warn!("{}, retrying without large pages", e);
+            let flags = flags - RandomXFlag::FLAG_LARGE_PAGES;
+            alloc(flags).map(|x| (x, flags))
+        }
+        res => res.map(|x| (x, flags)),
+    }
+}
+
+#[test]
+fn test_is_stale_tmp() {
+    assert!(is_stale_tmp("abcd.1234-0.tmp", 99));
+    assert!(is_stale_tmp("abcd-rx-wow.1234-3.tmp", 99));
+    assert!(!is_stale_tmp("abcd.99-0.tmp", 99));
+    assert!(!is_stale_tmp("abcd.rxds", 99));
+    assert!(!is_stale_tmp("notes.tmp", 99));
+}
+
+#[test]
+fn test_file_name() {
+    assert_eq!(file_name(Algorithm::Rx0, "abcd"), "abcd.rxds");
+    assert_eq!(file_name(Algorithm::RxWow, "abcd"), "abcd-rx-wow.rxds");
+}
@@ -0,0 +1,68 @@
+//! RandomX epoch switching.
+//!
+//! The seed hash changes once per epoch of 2048 blocks. The first worker to get a job for the new
+//! seed builds its dataset (see `Work::dataset`); every worker then moves its VM onto the new
+//! dataset, and the previous one is released once the last VM has left it. A job for another
+//! algorithm switches datasets the same way.
+
+use crate::utils::dataset::Dataset;
+use log::*;
+use randomx_rs::{Algorithm, RandomXFlag, RandomXVM};
+use std::sync::Arc;
+
+/// A worker's VM and the dataset it hashes over.
+pub struct EpochVm {
+    vm: RandomXVM,
+    dataset: Arc<Dataset>,
+    /// Flags the VM was asked for.
+    requested: RandomXFlag,
+    /// Flags that took effect, after falling back from large pages or to light mode.
+    flags: RandomXFlag,
+}
+
+impl EpochVm {
+    pub fn new(requested: RandomXFlag, dataset: Arc<Dataset>) -> Self {
+        let (vm, flags) = dataset.vm(requested);
+        info!("vm created with flags {:?}", flags);
+        EpochVm {
+            vm,
+            dataset,
+            requested,
+            flags,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.dataset.algorithm()
+    }
+
+    pub fn seed(&self) -> &str {
+        self.dataset.seed()
+    }
+
+    /// Flags the VM runs with.
+    pub fn flags(&self) -> RandomXFlag {
+        self.flags
+    }
+
+    pub fn vm(&mut self) -> &mut RandomXVM {
+        &mut self.vm
+    }
+
+    /// Move the VM onto `next`. The previous dataset is released once no VM uses it.
+    pub fn switch(&mut self, next: Arc<Dataset>) {
+        debug!("moving vm from seed {} to {}", self.seed(), next.seed());
+        if next.algorithm() != self.algorithm()
+            || next.is_light() == self.flags.contains(RandomXFlag::FLAG_FULL_MEM)
+        {
+            // a VM cannot move between algorithms, or between light and full mode
+            let (vm, flags) = next.vm(self.requested);
+            info!("vm recreated with flags {:?}", flags);
+            self.vm = vm;
+            self.flags = flags;
+        } else {
+            next.attach(&mut self.vm, self.flags);
+        }
+        self.dataset = next;
+    }
+}
@@ -4,8 +4,14 @@ use hex::FromHex;
 use log::*;
 use randomx_rs;
 
+pub mod bench;
 pub mod client;
 pub mod config;
+pub mod dataset;
+pub mod epoch;
+pub mod numa;
+pub mod selftest;
+pub mod verify;
 pub mod work;
 pub mod worker;
 
@@ -14,11 +20,11 @@ pub fn unhexlify(hexstr: &str) -> Result<[u8; 32], hex::FromHexError> {
     <[u8; 32]>::from_hex(hexstr)
 }
 
+/// Offset of the 4 nonce bytes in a hashing blob.
+pub const NONCE_OFFSET: usize = 39;
+
 pub fn pack_nonce(blob: &mut [u8], nonce_bytes: &[u8; 4]) {
-    blob[39] = nonce_bytes[0];
-    blob[40] = nonce_bytes[1];
-    blob[41] = nonce_bytes[2];
-    blob[42] = nonce_bytes[3];
+    blob[NONCE_OFFSET..NONCE_OFFSET + 4].copy_from_slice(nonce_bytes);
 }
 
 #[test]
@@ -150,7 +156,8 @@ fn rx_calculate_hash() {
 
     let rx_cache = randomx_rs::RandomXCache::new(rx_flags, &[0u8; 32]).unwrap();
     let rx_dataset = randomx_rs::RandomXDataset::new(rx_flags, &rx_cache, 0).unwrap();
-    let rx_vm = randomx_rs::RandomXVM::new(rx_flags, Some(&rx_cache), Some(&rx_dataset)).unwrap();
+    let mut rx_vm =
+        randomx_rs::RandomXVM::new(rx_flags, Some(&rx_cache), Some(&rx_dataset)).unwrap();
 
     let mut rx_hash = [0u8; 32];
     while nonce <= 1000 {
@@ -0,0 +1,109 @@
+//! NUMA topology, read from `/sys/devices/system/node`.
+//!
+//! On a machine with several nodes, each node gets its own dataset, initialized by threads pinned
+//! to that node's cores, so the kernel places its pages in the node's local memory. Workers then
+//! hash over the dataset of the node their core belongs to.
+
+use log::*;
+use std::fs;
+use std::path::Path;
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+
+/// The CPUs of each NUMA node.
+#[derive(Debug)]
+pub struct Topology {
+    nodes: Vec<(usize, Vec<usize>)>,
+}
+
+impl Topology {
+    /// Read the topology of this machine. Without NUMA support, every CPU is on node 0.
+    pub fn detect() -> Self {
+        Topology::read(Path::new(NODE_DIR)).unwrap_or_else(|e| {
+            debug!("no numa topology in {}: {}", NODE_DIR, e);
+            Topology { nodes: Vec::new() }
+        })
+    }
+
+    fn read(dir: &Path) -> std::io::Result<Self> {
+        let mut nodes = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let node = match name.to_str().and_then(|n| n.strip_prefix("node")) {
+                Some(id) => match id.parse::<usize>() {
+                    Ok(node) => node,
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+            let cpulist = fs::read_to_string(entry.path().join("cpulist"))?;
+            match parse_cpulist(&cpulist) {
+                Some(cpus) => nodes.push((node, cpus)),
+                None => warn!("invalid cpulist for numa node {}: {:?}", node, cpulist),
+            }
+        }
+        nodes.sort();
+        Ok(Topology { nodes })
+    }
+
+    /// Number of nodes, at least 1.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len().max(1)
+    }
+
+    /// Node of `cpu`, 0 if it is not listed.
+    pub fn node_of(&self, cpu: usize) -> usize {
+        self.nodes
+            .iter()
+            .find(|(_, cpus)| cpus.contains(&cpu))
+            .map_or(0, |(node, _)| *node)
+    }
+
+    /// The first CPU of `node` that is not in `busy`, if any.
+    pub fn spare_cpu(&self, node: usize, busy: &[usize]) -> Option<usize> {
+        let (_, cpus) = self.nodes.iter().find(|(n, _)| *n == node)?;
+        cpus.iter().copied().find(|cpu| !busy.contains(cpu))
+    }
+}
+
+/// Parse a kernel CPU list such as `0-3,8-11`.
+fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let mut bounds = range.splitn(2, '-');
+        let first = bounds.next()?.parse::<usize>().ok()?;
+        let last = match bounds.next() {
+            Some(last) => last.parse::<usize>().ok()?,
+            None => first,
+        };
+        cpus.extend(first..=last);
+    }
+    Some(cpus)
+}
+
+#[test]
+fn test_parse_cpulist() {
+    assert_eq!(parse_cpulist("0\n"), Some(vec![0]));
+    assert_eq!(
+        parse_cpulist("0-3,8-10\n"),
+        Some(vec![0, 1, 2, 3, 8, 9, 10])
+    );
+    assert_eq!(parse_cpulist("\n"), Some(vec![]));
+    assert_eq!(parse_cpulist("0-x"), None);
+}
+
+#[test]
+fn test_node_of() {
+    let topology = Topology {
+        nodes: vec![(0, vec![0, 1, 4, 5]), (1, vec![2, 3, 6, 7])],
+    };
+    assert_eq!(topology.node_count(), 2);
+    assert_eq!(topology.node_of(5), 0);
+    assert_eq!(topology.node_of(6), 1);
+    assert_eq!(topology.node_of(64), 0);
+    assert_eq!(Topology { nodes: Vec::new() }.node_count(), 1);
+    assert_eq!(topology.spare_cpu(1, &[0, 2, 3]), Some(6));
+    assert_eq!(topology.spare_cpu(0, &[0, 1, 4, 5]), None);
+    assert_eq!(topology.spare_cpu(2, &[]), None);
+}
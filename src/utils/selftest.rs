@@ -0,0 +1,65 @@
+//! Startup self-test of the RandomX flags against the reference test vectors.
+//!
+//! A miscompiled JIT or a wrong `hard_aes` gives wrong hashes without any error, so the flags are
+//! checked before mining, and the features most likely at fault are dropped until they pass.
+
+use crate::utils::dataset::Dataset;
+use log::*;
+use randomx_rs::{Algorithm, RandomXError, RandomXFlag};
+
+/// `flags`, then without the JIT, then also without hardware AES and optimized Argon2.
+fn candidates(flags: RandomXFlag) -> [RandomXFlag; 3] {
+    let no_jit = flags - RandomXFlag::FLAG_JIT - RandomXFlag::FLAG_SECURE;
+    let portable = no_jit - RandomXFlag::FLAG_HARD_AES - RandomXFlag::FLAG_ARGON2;
+    [flags, no_jit, portable]
+}
+
+/// Return the first flag set, starting with `flags`, that passes the self-test of every one of
+/// `algorithms`, or `None` if even the portable implementation fails.
+///
+/// The test runs in light mode, and without large pages, which only change how memory is
+/// allocated; see `check_dataset` for full mode.
+pub fn select_flags(flags: RandomXFlag, algorithms: &[Algorithm]) -> Option<RandomXFlag> {
+    candidates(flags).iter().copied().find(|&candidate| {
+        algorithms.iter().all(|&algorithm| {
+            let flags = candidate - RandomXFlag::FLAG_LARGE_PAGES;
+            match randomx_rs::self_test_algorithm(algorithm, flags) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("{} self-test failed: {}", algorithm, e);
+                    false
+                }
+            }
+        })
+    })
+}
+
+/// Check full mode with `flags` on `dataset`: hash fixed inputs with a VM over the dataset and
+/// with the portable implementation over its cache in light mode, which must agree.
+pub fn check_dataset(dataset: &Dataset, flags: RandomXFlag) -> Result<(), RandomXError> {
+    let (mut full, flags) = dataset.vm(flags);
+    let (mut light, _) = dataset.vm(RandomXFlag::FLAG_DEFAULT);
+    for index in 0..4 {
+        let input = format!("self-test input {}", index);
+        if full.calculate_hash(input.as_bytes())? != light.calculate_hash(input.as_bytes())? {
+            return Err(RandomXError::SelfTestFailed { flags, index });
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_candidates() {
+    let flags = RandomXFlag::FLAG_JIT
+        | RandomXFlag::FLAG_SECURE
+        | RandomXFlag::FLAG_HARD_AES
+        | RandomXFlag::FLAG_ARGON2_AVX2
+        | RandomXFlag::FLAG_FULL_MEM;
+    let [first, no_jit, portable] = candidates(flags);
+    assert_eq!(first, flags);
+    assert_eq!(
+        no_jit,
+        RandomXFlag::FLAG_HARD_AES | RandomXFlag::FLAG_ARGON2_AVX2 | RandomXFlag::FLAG_FULL_MEM
+    );
+    assert_eq!(portable, RandomXFlag::FLAG_FULL_MEM);
+}
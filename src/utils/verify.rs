@@ -0,0 +1,67 @@
+//! Share verification in a light-mode VM.
+//!
+//! With `verify_shares`, every share is hashed again before it is submitted, by the interpreter
+//! over caches of its own, so a hash that an unstable overclock or JIT got wrong is caught here
+//! instead of being rejected by the pool.
+
+use crate::utils::{pack_nonce, unhexlify};
+use byteorder::{ByteOrder, LE};
+use cn_stratum::client::Job;
+use log::*;
+use randomx_rs::{Algorithm, RandomXFlag, Verifier};
+
+pub struct ShareVerifier {
+    /// One verifier for each algorithm randomx-rs was built with.
+    verifiers: Vec<Verifier>,
+}
+
+impl ShareVerifier {
+    /// Create a verifier from the mining `flags`, keeping only those that do not change how
+    /// hashes are computed. Caches are kept for the current and the previous seed of each
+    /// algorithm, for shares found just before an epoch switch.
+    pub fn new(flags: RandomXFlag) -> Self {
+        let flags = flags
+            & (RandomXFlag::FLAG_HARD_AES
+                | RandomXFlag::FLAG_ARGON2_SSSE3
+                | RandomXFlag::FLAG_ARGON2_AVX2);
+        ShareVerifier {
+            verifiers: Algorithm::available()
+                .into_iter()
+                .map(|algorithm| Verifier::with_algorithm(algorithm, flags, 2))
+                .collect(),
+        }
+    }
+
+    /// Hash the job blob with `nonce` again with `algorithm`, and check that it gives `hash` and
+    /// meets the job target.
+    pub fn verify(&self, job: &Job, algorithm: Algorithm, nonce: u32, hash: &[u8; 32]) -> bool {
+        let mut blob = job.blob.clone();
+        pack_nonce(&mut blob, &nonce.to_le_bytes());
+        let seed = unhexlify(&job.seed_hash()).expect("unhexlify error");
+        let verifier = self
+            .verifiers
+            .iter()
+            .find(|verifier| verifier.algorithm() == algorithm)
+            .expect("share hashed with an algorithm that is not built in");
+        let expected = verifier.hash(&seed, &blob).unwrap();
+        if expected != *hash {
+            error!(
+                "hardware error: job {} nonce {} hashed to {} instead of {}",
+                job.job_id(),
+                nonce,
+                hex::encode(hash),
+                hex::encode(expected)
+            );
+            return false;
+        }
+        if LE::read_u64(&expected[24..]) > job.target() {
+            error!(
+                "hardware error: job {} nonce {} does not meet the target",
+                job.job_id(),
+                nonce
+            );
+            return false;
+        }
+        true
+    }
+}
@@ -1,8 +1,12 @@
-use cn_stratum::client::{
-    ErrorReply, Job, JobAssignment, MessageHandler, PoolClient, PoolClientWriter, RequestId,
-};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use crate::utils::dataset::{Dataset, DatasetParams};
+use cn_stratum::client::Job;
+use core_affinity::CoreId;
+use log::*;
+use randomx_rs::Algorithm;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub struct JobId(usize);
@@ -10,13 +14,34 @@ pub struct JobId(usize);
 pub struct Work {
     job_id: AtomicUsize,
     job: Mutex<Job>,
+    /// Datasets by NUMA node.
+    nodes: Mutex<BTreeMap<usize, Arc<NodeDatasets>>>,
+}
+
+/// The current and upcoming dataset of one NUMA node.
+struct NodeDatasets {
+    /// Only ever held briefly, so the stats can always read it.
+    dataset: Mutex<Option<Arc<Dataset>>>,
+    /// Held while building the current dataset, so that only one worker builds it.
+    building: Mutex<()>,
+    upcoming: Mutex<Upcoming>,
+    upcoming_ready: Condvar,
+}
+
+/// Dataset for the next epoch, built in the background before the pool switches seeds.
+enum Upcoming {
+    Empty,
+    /// Being built; setting the flag makes the build stop and fail.
+    Building(Algorithm, String, Arc<AtomicBool>),
+    Ready(Arc<Dataset>),
 }
 
 impl Work {
     pub fn new(job: Job) -> Self {
         let job_id = AtomicUsize::new(0);
         let job = Mutex::new(job);
-        Work { job_id, job }
+        let nodes = Mutex::new(BTreeMap::new());
+        Work { job_id, job, nodes }
     }
     pub fn is_current(&self, jid: JobId) -> bool {
         jid == JobId(self.job_id.load(Ordering::Relaxed))
@@ -31,4 +56,163 @@ impl Work {
         *self.job.lock().unwrap() = j;
         self.job_id.fetch_add(1, Ordering::Release);
     }
+
+    fn node(&self, node: usize) -> Arc<NodeDatasets> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let datasets = nodes.entry(node).or_insert_with(|| {
+            Arc::new(NodeDatasets {
+                dataset: Mutex::new(None),
+                building: Mutex::new(()),
+                upcoming: Mutex::new(Upcoming::Empty),
+                upcoming_ready: Condvar::new(),
+            })
+        });
+        Arc::clone(datasets)
+    }
+
+    /// Return the dataset of `algorithm` for `seed` on the node of `params`, building it if no
+    /// worker on the node has done so yet and it was not prepared in advance. Only one dataset is
+    /// kept per node; workers still holding the previous one keep it alive until they switch over.
+    pub fn dataset(
+        &self,
+        params: &DatasetParams,
+        algorithm: Algorithm,
+        seed: &str,
+    ) -> Arc<Dataset> {
+        let node = self.node(params.node);
+        if let Some(ds) = node.current(algorithm, seed) {
+            return ds;
+        }
+        let _building = node.building.lock().unwrap();
+        // another worker may have built it while we waited
+        if let Some(ds) = node.current(algorithm, seed) {
+            return ds;
+        }
+        let ds = match node.take_upcoming(algorithm, seed) {
+            Some(ds) => {
+                info!(
+                    "switching to precomputed dataset for seed {} on node {}",
+                    seed, params.node
+                );
+                ds
+            }
+            None => Arc::new(Dataset::new(params, algorithm, seed)),
+        };
+        *node.dataset.lock().unwrap() = Some(Arc::clone(&ds));
+        let saving = Arc::clone(&ds);
+        thread::Builder::new()
+            .name("save-dataset".into())
+            .spawn(move || saving.save())
+            .unwrap();
+        ds
+    }
+
+    /// The datasets for the current algorithm and seed by node, for those that have been built.
+    pub fn current_datasets(&self) -> Vec<(usize, Arc<Dataset>)> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .iter()
+            .filter_map(|(node, datasets)| {
+                let dataset = datasets.dataset.lock().unwrap();
+                dataset.as_ref().map(|ds| (*node, Arc::clone(ds)))
+            })
+            .collect()
+    }
+
+    /// Start building the dataset of `algorithm` for `seed` on the node of `params` on a
+    /// background thread, unless it is already the current or upcoming one.
+    pub fn prepare(&self, params: &DatasetParams, algorithm: Algorithm, seed: &str) {
+        let node = self.node(params.node);
+        if node.current(algorithm, seed).is_some() {
+            return;
+        }
+        let mut upcoming = node.upcoming.lock().unwrap();
+        match &*upcoming {
+            Upcoming::Building(a, s, _) if *a == algorithm && s == seed => return,
+            Upcoming::Ready(ds) if ds.is_for(algorithm, seed) => return,
+            // superseded before it was needed
+            Upcoming::Building(_, _, cancel) => cancel.store(true, Ordering::Relaxed),
+            _ => (),
+        }
+        info!(
+            "precomputing {} dataset for next seed {} on node {}",
+            algorithm, seed, params.node
+        );
+        let cancel = Arc::new(AtomicBool::new(false));
+        *upcoming = Upcoming::Building(algorithm, seed.to_owned(), Arc::clone(&cancel));
+        let seed = seed.to_owned();
+        let params = params.clone();
+        let datasets = Arc::clone(&node);
+        thread::Builder::new()
+            .name("precompute".into())
+            .spawn(move || {
+                // touch the dataset memory from the node it is for, without taking a worker's core
+                if let Some(cpu) = params.spare_cpu {
+                    core_affinity::set_for_current(CoreId { id: cpu });
+                }
+                // next to the current dataset, memory is short: rather than keep a dataset without
+                // large pages (or none at all) for the whole next epoch, build it at the switch;
+                // on this thread only, to leave the cores to the workers
+                let built = Dataset::precompute(&params, algorithm, &seed, &cancel).map(Arc::new);
+                match &built {
+                    Err(_) if cancel.load(Ordering::Relaxed) => {
+                        info!("stopped precomputing dataset for seed {}", seed)
+                    }
+                    Err(e) => warn!(
+                        "cannot precompute dataset for seed {} ({}), building it at the switch",
+                        seed, e
+                    ),
+                    Ok(_) => (),
+                }
+                let mut upcoming = datasets.upcoming.lock().unwrap();
+                if let Upcoming::Building(a, s, _) = &*upcoming {
+                    if *a == algorithm && *s == seed {
+                        *upcoming = match &built {
+                            Ok(ds) => {
+                                debug!("dataset for seed {} ready", seed);
+                                Upcoming::Ready(Arc::clone(ds))
+                            }
+                            Err(_) => Upcoming::Empty,
+                        };
+                    }
+                }
+                datasets.upcoming_ready.notify_all();
+                drop(upcoming);
+                if let Ok(ds) = built {
+                    ds.save();
+                }
+            })
+            .unwrap();
+    }
+}
+
+impl NodeDatasets {
+    /// The current dataset, if it is the one of `algorithm` for `seed`.
+    fn current(&self, algorithm: Algorithm, seed: &str) -> Option<Arc<Dataset>> {
+        match &*self.dataset.lock().unwrap() {
+            Some(ds) if ds.is_for(algorithm, seed) => Some(Arc::clone(ds)),
+            _ => None,
+        }
+    }
+
+    /// Take the upcoming dataset if it is the one of `algorithm` for `seed`. If it is still being
+    /// built on its one thread, cancel that, since building it now on all the node's init threads
+    /// is faster; the wait is only for the build to stop and free its memory.
+    fn take_upcoming(&self, algorithm: Algorithm, seed: &str) -> Option<Arc<Dataset>> {
+        let mut upcoming = self.upcoming.lock().unwrap();
+        loop {
+            match &*upcoming {
+                Upcoming::Building(a, s, cancel) if *a == algorithm && s == seed => {
+                    cancel.store(true, Ordering::Relaxed);
+                    upcoming = self.upcoming_ready.wait(upcoming).unwrap();
+                }
+                Upcoming::Ready(ds) if ds.is_for(algorithm, seed) => {
+                    let ds = Arc::clone(ds);
+                    *upcoming = Upcoming::Empty;
+                    return Some(ds);
+                }
+                _ => return None,
+            }
+        }
+    }
 }
@@ -1,83 +1,113 @@
-use crate::utils::pack_nonce;
-use crate::utils::unhexlify;
+use crate::utils::dataset::DatasetParams;
+use crate::utils::epoch::EpochVm;
+use crate::utils::verify::ShareVerifier;
 use crate::utils::work::Work;
+use crate::utils::{pack_nonce, NONCE_OFFSET};
 use byteorder::{ByteOrder, LE};
-use cn_stratum::client::{
-    ErrorReply, Job, JobAssignment, MessageHandler, PoolClient, PoolClientWriter, RequestId,
-};
-use core_affinity::CoreId;
-use hex::FromHex;
+use cn_stratum::client::{Job, PoolClientWriter};
 use log::*;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc;
+use randomx_rs::Algorithm;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub struct Worker {
     pub hash_count: Arc<AtomicUsize>,
+    /// Bits of the flags the worker's VM runs with, for the stats output.
+    pub vm_flags: Arc<AtomicU32>,
     pub work: Arc<Work>,
-    pub core: CoreId,
     pub worker_id: u32,
     pub step: u32,
+    pub dataset: DatasetParams,
+    pub precompute: bool,
+    /// Algorithm to mine every job with, instead of the one the job names.
+    pub algorithm: Option<Algorithm>,
 }
 
 impl Worker {
-    pub fn run(self, rx_flags: randomx_rs::RandomXFlag, tx: Sender<Res>) -> () {
+    pub fn run(self, rx_flags: randomx_rs::RandomXFlag, tx: Sender<Res>) {
         debug!("init worker");
 
-        let (_, job) = self.work.current();
-
-        let mut current_seed = job.seed_hash();
-        let seed_hash = <[u8; 32]>::from_hex(&job.seed_hash()).unwrap();
-
-        let rx_cache = randomx_rs::RandomXCache::new(rx_flags, &seed_hash).unwrap();
-        debug!("[worker] cache created");
-        let rx_dataset = randomx_rs::RandomXDataset::new(rx_flags, &rx_cache, 0).unwrap();
-        debug!("[worker] dataset created");
-        let rx_vm =
-            randomx_rs::RandomXVM::new(rx_flags, Some(&rx_cache), Some(&rx_dataset)).unwrap();
-        debug!("[worker] randomx vm created");
+        let mut epoch_vm: Option<EpochVm> = None;
+        let mut skipped = None;
 
         loop {
             trace!("getting work");
             let (jid, job) = self.work.current();
 
+            let algorithm = match job_algorithm(self.algorithm, &job) {
+                Some(algorithm) => algorithm,
+                None => {
+                    if skipped != Some(jid) {
+                        error!(
+                            "cannot mine algorithm {} of job {}, waiting for the next job",
+                            job.algo().unwrap_or_default(),
+                            job.job_id()
+                        );
+                        skipped = Some(jid);
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+            };
+
             let target = job.target();
             let mut blob_hash = job.blob.clone();
 
-            if current_seed != job.seed_hash() {
-                current_seed = job.seed_hash();
-                let current_seed_hash = unhexlify(&job.seed_hash()).expect("unhexlify error");
-                debug!("new seed: {:?}", current_seed_hash);
-                debug!("reinit vm");
-                drop(&rx_cache);
-                drop(&rx_dataset);
-                let rx_cache = randomx_rs::RandomXCache::new(rx_flags, &current_seed_hash).unwrap();
-                let rx_dataset = randomx_rs::RandomXDataset::new(rx_flags, &rx_cache, 0).unwrap();
-                rx_vm
-                    .reinit_cache(&rx_cache)
-                    .expect("error reinitializing cache");
-                rx_vm
-                    .reinit_dataset(&rx_dataset)
-                    .expect("error reinitializing cache");
+            if let Some(next) = job.next_seed_hash() {
+                if self.precompute && next != job.seed_hash() {
+                    self.work.prepare(&self.dataset, algorithm, next);
+                }
             }
 
-            let start = (u32::from(blob_hash[42]) << 24) + self.worker_id;
-            let nonce_seq = (start..).step_by(self.step as usize);
-            let mut rx_hash = [0u8; 32];
-            for nonce in nonce_seq {
-                if !self.work.is_current(jid) {
-                    break;
+            let rx = match &mut epoch_vm {
+                Some(rx) => rx,
+                None => {
+                    let dataset = self
+                        .work
+                        .dataset(&self.dataset, algorithm, &job.seed_hash());
+                    debug!("[worker] randomx vm created");
+                    epoch_vm.get_or_insert(EpochVm::new(rx_flags, dataset))
                 }
-                pack_nonce(&mut blob_hash, &nonce.to_le_bytes());
-                rx_hash = rx_vm.calculate_hash(&mut blob_hash).unwrap();
+            };
+            if rx.algorithm() != algorithm {
+                info!("switching from {} to {}", rx.algorithm(), algorithm);
+                rx.switch(
+                    self.work
+                        .dataset(&self.dataset, algorithm, &job.seed_hash()),
+                );
+            } else if rx.seed() != job.seed_hash() {
+                debug!("new seed: {}", job.seed_hash());
+                rx.switch(
+                    self.work
+                        .dataset(&self.dataset, algorithm, &job.seed_hash()),
+                );
+            }
+            self.vm_flags.store(rx.flags().bits(), Ordering::Relaxed);
+
+            let start = (u32::from(blob_hash[42]) << 24) + self.worker_id;
+            let nonce_seq = (start..)
+                .step_by(self.step as usize)
+                .take_while(|_| self.work.is_current(jid));
+            let hashes = rx
+                .vm()
+                .hash_nonces(&mut blob_hash, NONCE_OFFSET, nonce_seq)
+                .unwrap();
+            for (nonce, rx_hash) in hashes {
                 if LE::read_u64(&rx_hash[24..]) <= target {
-                    debug!("submitting share {:?}", blob_hash);
-                    tx.send(Res {
+                    debug!("submitting share for nonce {}", nonce);
+                    let res = Res {
                         job: job.clone(),
-                        nonce: nonce,
-                        hash: rx_hash.clone(),
-                    });
+                        algorithm,
+                        nonce,
+                        hash: rx_hash,
+                    };
+                    if tx.send(res).is_err() {
+                        error!("share sender stopped, worker{} exiting", self.worker_id);
+                        return;
+                    }
                     /*
                     self.pool
                         .lock()
@@ -90,16 +120,81 @@ impl Worker {
             }
         }
     }
+
+    /// Hash the nonces `worker_id, worker_id + step, ...` below `nonces` over the current job with
+    /// rx/0, for `bench`. Returns the XOR of the hashes and the time spent hashing.
+    pub fn bench(self, rx_flags: randomx_rs::RandomXFlag, nonces: u32) -> ([u8; 32], Duration) {
+        let (_, job) = self.work.current();
+        let mut blob = job.blob.clone();
+        let dataset = self
+            .work
+            .dataset(&self.dataset, Algorithm::Rx0, &job.seed_hash());
+        let mut rx = EpochVm::new(rx_flags, dataset);
+        self.vm_flags.store(rx.flags().bits(), Ordering::Relaxed);
+
+        let start = Instant::now();
+        let nonce_seq = (self.worker_id..nonces).step_by(self.step as usize);
+        let mut result = [0u8; 32];
+        let mut last = None;
+        let hashes = rx
+            .vm()
+            .hash_nonces(&mut blob, NONCE_OFFSET, nonce_seq)
+            .unwrap();
+        for (nonce, rx_hash) in hashes {
+            for (x, y) in result.iter_mut().zip(rx_hash.iter()) {
+                *x ^= y;
+            }
+            last = Some((nonce, rx_hash));
+            self.hash_count.fetch_add(1, Ordering::Relaxed);
+        }
+        let elapsed = start.elapsed();
+
+        // check the pipelined hashes against a plain one
+        if let Some((nonce, rx_hash)) = last {
+            pack_nonce(&mut blob, &nonce.to_le_bytes());
+            assert_eq!(rx.vm().calculate_hash(&blob).unwrap(), rx_hash);
+        }
+        (result, elapsed)
+    }
+}
+
+/// The algorithm to mine `job` with: `configured` if set, otherwise the one the job names, or
+/// rx/0 if it names none. `None` if it is unknown or not built into randomx-rs.
+pub fn job_algorithm(configured: Option<Algorithm>, job: &Job) -> Option<Algorithm> {
+    let algorithm = match configured {
+        Some(algorithm) => algorithm,
+        None => match job.algo() {
+            Some(name) => Algorithm::from_name(name)?,
+            None => Algorithm::Rx0,
+        },
+    };
+    if algorithm.is_available() {
+        Some(algorithm)
+    } else {
+        None
+    }
+}
+
+/// The stratum name of the algorithm `name` stands for, resolving the aliases `job_algorithm`
+/// accepts, so the pool client matches jobs the way the workers mine them.
+pub fn canonical_algorithm(name: &str) -> Option<&'static str> {
+    Algorithm::from_name(name).map(Algorithm::name)
 }
 
-use crate::utils;
 pub struct Res {
     pub job: cn_stratum::client::Job,
+    /// Algorithm the share was hashed with.
+    pub algorithm: Algorithm,
     pub nonce: u32,
     pub hash: [u8; 32],
 }
 
-pub struct SubmitWorker {}
+pub struct SubmitWorker {
+    /// Recomputes each share before it is submitted, if `verify_shares` is set.
+    pub verifier: Option<ShareVerifier>,
+    /// Number of shares whose hash was wrong.
+    pub hw_errors: Arc<AtomicUsize>,
+}
 
 impl SubmitWorker {
     pub fn submit_share(self, rx: Receiver<Res>, pool: Arc<Mutex<PoolClientWriter>>) {
@@ -107,11 +202,56 @@ impl SubmitWorker {
             let res = rx.recv();
             match res {
                 Ok(r) => {
+                    if let Some(verifier) = &self.verifier {
+                        if !verifier.verify(&r.job, r.algorithm, r.nonce, &r.hash) {
+                            self.hw_errors.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+                    }
                     debug!("ready to submit nonce: {}", r.nonce);
-                    pool.lock().unwrap().submit(&r.job, r.nonce, &r.hash);
+                    if let Err(e) = pool.lock().unwrap().submit(&r.job, r.nonce, &r.hash) {
+                        warn!("failed to submit nonce {}: {}", r.nonce, e);
+                    }
                 }
                 Err(_) => error!("error submitting share"),
             }
         }
     }
 }
+
+#[test]
+fn test_job_algorithm() {
+    let job = |algo: Option<&str>| -> Job {
+        let json = format!(
+            concat!(
+                r#"{{"blob":"00","job_id":"1","target":"c5a70000","height":1,"#,
+                r#""seed_hash":"00","algo":{}}}"#
+            ),
+            serde_json::to_string(&algo).unwrap()
+        );
+        serde_json::from_str(&json).unwrap()
+    };
+    assert_eq!(job_algorithm(None, &job(None)), Some(Algorithm::Rx0));
+    assert_eq!(
+        job_algorithm(None, &job(Some("rx/0"))),
+        Some(Algorithm::Rx0)
+    );
+    assert_eq!(job_algorithm(None, &job(Some("cn/r"))), None);
+    assert_eq!(
+        job_algorithm(Some(Algorithm::Rx0), &job(Some("cn/r"))),
+        Some(Algorithm::Rx0)
+    );
+    assert_eq!(
+        job_algorithm(None, &job(Some("rx/wow"))).is_some(),
+        Algorithm::RxWow.is_available()
+    );
+}
+
+#[test]
+fn test_canonical_algorithm() {
+    assert_eq!(canonical_algorithm("rx/0"), Some("rx/0"));
+    assert_eq!(canonical_algorithm("randomx"), Some("rx/0"));
+    assert_eq!(canonical_algorithm("rx/monero"), Some("rx/0"));
+    assert_eq!(canonical_algorithm("rx/wow"), Some("rx/wow"));
+    assert_eq!(canonical_algorithm("cn/r"), None);
+}